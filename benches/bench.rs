@@ -15,8 +15,8 @@ use std::thread;
 /// Wait for both to finish.
 fn fork<T1, T2>(t1: T1, t2: T2)
 where
-    T1: FnOnce() -> () + Send + 'static,
-    T2: FnOnce() -> (),
+    T1: FnOnce() + Send + 'static,
+    T2: FnOnce(),
 {
     let (sender, receiver) = channel();
     thread::spawn(move || {
@@ -59,10 +59,10 @@ fn bench_stm_queue(b: &mut Bencher) {
         fork(
             move || for i in 0..1000 {
                 let x = atomically(|tx| queue.pop(tx));
-                assert_eq!(x, i);
+                assert_eq!(x, Ok(i));
             },
             || for i in 0..1000 {
-                atomically(|tx| queue2.push(tx, i));
+                atomically(|tx| queue2.push(tx, i)).unwrap();
             },
         );
     });
@@ -101,10 +101,10 @@ fn bench_stm_bqueue_1(b: &mut Bencher) {
         fork(
             move || for i in 0..1000 {
                 let x = atomically(|tx| queue.pop(tx));
-                assert_eq!(x, i);
+                assert_eq!(x, Ok(i));
             },
             || for i in 0..1000 {
-                atomically(|tx| queue2.push(tx, i));
+                atomically(|tx| queue2.push(tx, i)).unwrap();
             },
         );
     });
@@ -144,11 +144,33 @@ fn bench_stm_bqueue_200(b: &mut Bencher) {
         fork(
             move || for i in 0..1000 {
                 let x = atomically(|tx| queue.pop(tx));
-                assert_eq!(x, i);
+                assert_eq!(x, Ok(i));
             },
             || for i in 0..1000 {
-                atomically(|tx| queue2.push(tx, i));
+                atomically(|tx| queue2.push(tx, i)).unwrap();
             },
         );
     });
 }
+
+#[bench]
+/// Reverse a list with one million elements, that is shared with
+/// another list, so no node can be reused.
+fn bench_arclist_reverse_shared(b: &mut Bencher) {
+    let list: ArcList<usize> = (0..1_000_000).collect();
+
+    b.iter(|| {
+        let reversed = list.clone().reverse();
+        assert_eq!(reversed.head(), Some(&999_999));
+    });
+}
+
+#[bench]
+/// Iterate by value over a shared list with one million elements.
+fn bench_arclist_into_iter_shared(b: &mut Bencher) {
+    let list: ArcList<usize> = (0..1_000_000).collect();
+
+    b.iter(|| {
+        assert_eq!(list.clone().into_iter().count(), 1_000_000);
+    });
+}
@@ -1,6 +1,9 @@
 use std::sync::Arc;
+use std::fmt;
+use std::hash::{Hash, Hasher};
+use std::cmp::Ordering;
+use std::iter::FromIterator;
 
-#[derive(Debug)]
 pub struct ArcList<T> {
     head: Option<Arc<(T, ArcList<T>)>>,
 }
@@ -11,6 +14,12 @@ impl<T> Clone for ArcList<T> {
     }
 }
 
+impl<T> Default for ArcList<T> {
+    fn default() -> Self {
+        ArcList::new()
+    }
+}
+
 impl<T> ArcList<T> {
     /// Create a new, empty list.
     pub fn new() -> Self {
@@ -53,6 +62,74 @@ impl<T> ArcList<T> {
     pub fn take(&mut self) -> Self {
         ArcList { head: self.head.take() }
     }
+
+    /// Iterate over references to the elements of the list.
+    pub fn iter(&self) -> IterRef<'_, T> {
+        IterRef { list: self }
+    }
+
+    /// Count the elements of the list.
+    ///
+    /// This walks the whole list and therefore takes linear time.
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    /// Return a reference to the `n`th element of the list.
+    pub fn nth(&self, n: usize) -> Option<&T> {
+        self.iter().nth(n)
+    }
+
+    /// Return a reference to the last element of the list.
+    pub fn last(&self) -> Option<&T> {
+        self.iter().last()
+    }
+
+    /// Check if the list contains an element equal to `x`.
+    pub fn contains(&self, x: &T) -> bool
+    where
+        T: PartialEq,
+    {
+        self.iter().any(|e| e == x)
+    }
+
+    /// Return the list without the first `n` elements.
+    ///
+    /// The result shares all nodes with `self`.
+    pub fn skip(&self, n: usize) -> Self {
+        let mut list = self;
+        for _ in 0..n {
+            match list.as_ref() {
+                Some((_, xs)) => list = xs,
+                None => break,
+            }
+        }
+        list.clone()
+    }
+
+    /// Return a reference to the first element, that matches `pred`.
+    pub fn find<P>(&self, mut pred: P) -> Option<&T>
+    where
+        P: FnMut(&T) -> bool,
+    {
+        self.iter().find(|x| pred(x))
+    }
+
+    /// Combine all elements from front to back.
+    pub fn fold<B, F>(&self, init: B, f: F) -> B
+    where
+        F: FnMut(B, &T) -> B,
+    {
+        self.iter().fold(init, f)
+    }
+
+    /// Create a new list by applying `f` to every element.
+    pub fn map<U, F>(&self, f: F) -> ArcList<U>
+    where
+        F: FnMut(&T) -> U,
+    {
+        self.iter().map(f).collect()
+    }
 }
 
 impl<T: Clone> ArcList<T> {
@@ -67,25 +144,131 @@ impl<T: Clone> ArcList<T> {
     pub fn into_splitted(mut self) -> Option<(T, ArcList<T>)> {
         self.head.take().map(|h| match Arc::try_unwrap(h) {
             Ok(x) => x,
-            Err(rf) => (*rf).clone(),
+            // Only the value is copied, the tail is shared.
+            Err(rf) => (rf.0.clone(), rf.1.clone()),
         })
     }
 
+    /// Remove the first element.
+    ///
+    /// The value is moved out of the node, if no other list shares it.
+    /// Otherwise only the value is cloned and the tail stays shared.
     pub fn pop(&mut self) -> Option<T> {
-        self.take().into_splitted().map(|(x, xs)| {
-            *self = xs;
-            x
-        })
+        let h = self.head.take()?;
+        let (x, xs) = match Arc::try_unwrap(h) {
+            Ok(x) => x,
+            Err(rf) => (rf.0.clone(), rf.1.clone()),
+        };
+        *self = xs;
+        Some(x)
     }
 
     /// Reverse the list.
+    ///
+    /// The values of nodes owned by this list alone are moved into the new
+    /// list. Once a node is shared with another list, the rest is only
+    /// traversed by reference and its values are cloned.
     pub fn reverse(mut self) -> Self {
         let mut new_list = ArcList::new();
-        while let Some(t) = self.pop() {
-            new_list.push(t)
+        while let Some(h) = self.head.take() {
+            match Arc::try_unwrap(h) {
+                Ok((x, xs)) => {
+                    new_list.push(x);
+                    self = xs;
+                }
+                Err(h) => {
+                    self.head = Some(h);
+                    break;
+                }
+            }
+        }
+        for x in self.iter() {
+            new_list.push(x.clone());
         }
         new_list
     }
+
+    /// Return a list with the elements of `self` followed by `other`.
+    ///
+    /// The elements of `self` are copied, `other` is shared.
+    pub fn append(&self, other: &ArcList<T>) -> Self {
+        let elements: Vec<&T> = self.iter().collect();
+        let mut list = other.clone();
+        for x in elements.into_iter().rev() {
+            list = list.prepend(x.clone());
+        }
+        list
+    }
+
+    /// Return a list with the first `n` elements.
+    ///
+    /// This is not called `take`, because `take` already moves the whole
+    /// list out of `self`.
+    pub fn take_n(&self, n: usize) -> Self {
+        self.iter().take(n).cloned().collect()
+    }
+
+    /// Return a list with the elements, that match `pred`.
+    ///
+    /// The part after the last removed element is shared with `self`.
+    pub fn filter<P>(&self, pred: P) -> Self
+    where
+        P: FnMut(&T) -> bool,
+    {
+        let mut list = self.clone();
+        list.retain(pred);
+        list
+    }
+
+    /// Remove all elements, that don't match `pred`.
+    ///
+    /// The part after the last removed element stays shared with other lists.
+    pub fn retain<P>(&mut self, mut pred: P)
+    where
+        P: FnMut(&T) -> bool,
+    {
+        let keep: Vec<bool> = self.iter().map(&mut pred).collect();
+        let shared = match keep.iter().rposition(|&k| !k) {
+            Some(i) => i + 1,
+            None => return,
+        };
+        let copied: Vec<T> = self
+            .iter()
+            .zip(&keep)
+            .take(shared)
+            .filter(|&(_, &k)| k)
+            .map(|(x, _)| x.clone())
+            .collect();
+        let mut list = self.skip(shared);
+        for x in copied.into_iter().rev() {
+            list = list.prepend(x);
+        }
+        *self = list;
+    }
+
+    /// Create a list of pairs from the elements of two lists.
+    ///
+    /// The result is as long as the shorter one.
+    pub fn zip<U: Clone>(&self, other: &ArcList<U>) -> ArcList<(T, U)> {
+        self.iter()
+            .zip(other.iter())
+            .map(|(x, y)| (x.clone(), y.clone()))
+            .collect()
+    }
+}
+
+impl<T: Clone> ArcList<ArcList<T>> {
+    /// Join all lists into a single one.
+    ///
+    /// The last list is shared, all others are copied.
+    pub fn concat(&self) -> ArcList<T> {
+        let lists: Vec<&ArcList<T>> = self.iter().collect();
+        let mut list = ArcList::new();
+        for l in lists.into_iter().rev() {
+            list = l.append(&list);
+        }
+        list
+    }
 }
 
 impl<T> Drop for ArcList<T> {
@@ -101,10 +284,19 @@ impl<T> Drop for ArcList<T> {
     }
 }
 
+/// Iterator over references to the elements of an `ArcList`.
+///
+/// Created by `ArcList::iter`.
 pub struct IterRef<'a, T: 'a> {
     list: &'a ArcList<T>,
 }
 
+impl<'a, T> Clone for IterRef<'a, T> {
+    fn clone(&self) -> Self {
+        IterRef { list: self.list }
+    }
+}
+
 impl<'a, T> Iterator for IterRef<'a, T> {
     type Item = &'a T;
 
@@ -119,6 +311,12 @@ impl<'a, T> Iterator for IterRef<'a, T> {
     }
 }
 
+/// Iterator over the elements of an `ArcList` by value.
+///
+/// Created by `ArcList::into_iter`. Values are moved out of the nodes,
+/// that are owned by this list alone. Once a node is shared with another
+/// list, only its value is cloned and the tail is shared by cloning its
+/// `Arc`, so shared nodes are never copied.
 pub struct IterClone<T> {
     list: ArcList<T>,
 }
@@ -131,6 +329,101 @@ impl<T: Clone> Iterator for IterClone<T> {
     }
 }
 
+impl<'a, T> IntoIterator for &'a ArcList<T> {
+    type Item = &'a T;
+    type IntoIter = IterRef<'a, T>;
+
+    fn into_iter(self) -> IterRef<'a, T> {
+        self.iter()
+    }
+}
+
+impl<T: Clone> IntoIterator for ArcList<T> {
+    type Item = T;
+    type IntoIter = IterClone<T>;
+
+    fn into_iter(self) -> IterClone<T> {
+        IterClone { list: self }
+    }
+}
+
+impl<T> FromIterator<T> for ArcList<T> {
+    /// Build a list with the elements in iteration order.
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let elements: Vec<T> = iter.into_iter().collect();
+        let mut list = ArcList::new();
+        for x in elements.into_iter().rev() {
+            list = list.prepend(x);
+        }
+        list
+    }
+}
+
+impl<T> Extend<T> for ArcList<T> {
+    /// Push all elements to the front of the list.
+    ///
+    /// Just like repeated calls to `push` the elements end up in reverse
+    /// order, so `list.extend(v)` on an empty list gives the reverse of
+    /// `v.into_iter().collect()`. Appending to the end would copy the
+    /// whole list. Use `append` for that.
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for x in iter {
+            self.push(x);
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for ArcList<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<T: PartialEq> PartialEq for ArcList<T> {
+    fn eq(&self, other: &Self) -> bool {
+        let mut a = self;
+        let mut b = other;
+        loop {
+            match (&a.head, &b.head) {
+                (Some(x), Some(y)) => {
+                    // Shared tails are still compared element by element,
+                    // because `PartialEq` needs not be reflexive (NaN).
+                    if x.0 != y.0 {
+                        return false;
+                    }
+                    a = &x.1;
+                    b = &y.1;
+                }
+                (None, None) => return true,
+                _ => return false,
+            }
+        }
+    }
+}
+
+impl<T: Eq> Eq for ArcList<T> {}
+
+impl<T: PartialOrd> PartialOrd for ArcList<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        self.iter().partial_cmp(other.iter())
+    }
+}
+
+impl<T: Ord> Ord for ArcList<T> {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.iter().cmp(other.iter())
+    }
+}
+
+impl<T: Hash> Hash for ArcList<T> {
+    fn hash<H: Hasher>(&self, state: &mut H) {
+        self.len().hash(state);
+        for x in self {
+            x.hash(state);
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -159,4 +452,145 @@ mod tests {
 
         assert_eq!(Some(&1), list.head());
     }
+
+    /// Test if `collect` keeps the order and both iterators return it.
+    #[test]
+    fn test_arclist_iter() {
+        let list: ArcList<i32> = (1..4).collect();
+
+        assert_eq!(vec![&1, &2, &3], list.iter().collect::<Vec<_>>());
+        assert_eq!(vec![1, 2, 3], list.clone().into_iter().collect::<Vec<_>>());
+        assert_eq!(3, list.len());
+        assert_eq!(Some(&2), list.nth(1));
+        assert_eq!(Some(&3), list.last());
+        assert!(list.contains(&3));
+        assert!(!list.contains(&4));
+    }
+
+    /// Test if `extend` behaves like repeated calls to `push`.
+    #[test]
+    fn test_arclist_extend() {
+        let mut list = ArcList::new();
+        list.extend(1..4);
+
+        assert_eq!(vec![3, 2, 1], list.clone().into_iter().collect::<Vec<_>>());
+        // `collect` keeps the order, `extend` reverses it.
+        let collected: ArcList<_> = (1..4).collect();
+        assert_eq!(collected.reverse(), list);
+        list.extend(4..6);
+        assert_eq!(vec![5, 4, 3, 2, 1], list.into_iter().collect::<Vec<_>>());
+    }
+
+    /// Test the operations, that create new lists.
+    #[test]
+    fn test_arclist_ops() {
+        let a: ArcList<i32> = (1..6).collect();
+        let b: ArcList<i32> = (6..8).collect();
+
+        let ab = a.append(&b);
+        assert_eq!((1..8).collect::<ArcList<_>>(), ab);
+        assert!(Arc::ptr_eq(ab.skip(5).head.as_ref().unwrap(), b.head.as_ref().unwrap()));
+
+        assert_eq!((1..3).collect::<ArcList<_>>(), a.take_n(2));
+        assert_eq!((3..6).collect::<ArcList<_>>(), a.skip(2));
+        assert!(a.skip(10).is_empty());
+        assert_eq!((2..7).collect::<ArcList<_>>(), a.map(|x| x + 1));
+        assert_eq!(15, a.fold(0, |acc, x| acc + x));
+        assert_eq!(Some(&4), a.find(|&x| x > 3));
+
+        let even = a.filter(|&x| x % 2 == 0);
+        assert_eq!(vec![2, 4], even.into_iter().collect::<Vec<_>>());
+        let mut c = ab.clone();
+        c.retain(|&x| x != 3);
+        assert_eq!(vec![1, 2, 4, 5, 6, 7], c.iter().cloned().collect::<Vec<_>>());
+        assert!(Arc::ptr_eq(c.skip(2).head.as_ref().unwrap(), ab.skip(3).head.as_ref().unwrap()));
+
+        let pairs = a.zip(&b);
+        assert_eq!(vec![(1, 6), (2, 7)], pairs.into_iter().collect::<Vec<_>>());
+
+        let lists: ArcList<ArcList<i32>> = vec![a.clone(), ArcList::new(), b.clone()].into_iter().collect();
+        assert_eq!(ab, lists.concat());
+    }
+
+    /// The new operations must not overflow the stack on long lists.
+    #[test]
+    fn test_long_list_ops() {
+        let a: ArcList<i32> = (0..100000).collect();
+        let b = a.append(&a);
+        assert_eq!(200000, b.len());
+        assert_eq!(50000, a.take_n(50000).len());
+        assert_eq!(Some(&99999), a.skip(99999).head());
+        assert_eq!(100000, a.map(|&x| x as i64).fold(0, |acc, _| acc + 1));
+        assert_eq!(50000, a.filter(|&x| x % 2 == 0).len());
+        assert_eq!(100000, a.zip(&a).len());
+        assert_eq!(None, a.find(|&x| x < 0));
+        let lists: ArcList<ArcList<i32>> = vec![a.clone(), a.clone()].into_iter().collect();
+        assert_eq!(b, lists.concat());
+    }
+
+    /// Reversing a partly shared list leaves the other list intact.
+    #[test]
+    fn test_arclist_reverse_shared() {
+        let shared: ArcList<i32> = (3..100000).collect();
+        let list = shared.clone().prepend(2).prepend(1);
+
+        let reversed = list.reverse();
+        assert_eq!(Some(&99999), reversed.head());
+        assert_eq!(Some(&1), reversed.last());
+        assert_eq!(99999, reversed.len());
+        assert_eq!((3..100000).collect::<ArcList<_>>(), shared);
+
+        let mut values = shared.clone().into_iter();
+        assert_eq!(Some(3), values.next());
+        assert_eq!(99996, values.count());
+        assert_eq!(99997, shared.len());
+    }
+
+    /// Iterating by value moves the values out of owned nodes and leaves
+    /// shared nodes intact.
+    #[test]
+    fn test_arclist_into_iter_shared() {
+        let shared: ArcList<Arc<i32>> = (1..4).map(Arc::new).collect();
+        let first = Arc::new(0);
+        let list = shared.clone().prepend(first.clone());
+
+        let mut values = list.into_iter();
+        let x = values.next().unwrap();
+        assert!(Arc::ptr_eq(&first, &x));
+        assert_eq!(2, Arc::strong_count(&first));
+
+        let second = values.next().unwrap();
+        assert!(Arc::ptr_eq(shared.head().unwrap(), &second));
+        assert_eq!(vec![2, 3], values.map(|x| *x).collect::<Vec<_>>());
+        assert_eq!(3, shared.len());
+    }
+
+    /// Comparing and printing long lists must not overflow the stack.
+    #[test]
+    fn test_long_list_traits() {
+        use std::collections::hash_map::DefaultHasher;
+
+        let a: ArcList<i32> = (0..100000).collect();
+        let b: ArcList<i32> = (0..100000).collect();
+        let c = a.clone().prepend(-1);
+
+        assert_eq!(a, b);
+        assert!(a != c);
+        assert_eq!(Ordering::Less, c.cmp(&a));
+
+        let mut h1 = DefaultHasher::new();
+        let mut h2 = DefaultHasher::new();
+        a.hash(&mut h1);
+        b.hash(&mut h2);
+        assert_eq!(h1.finish(), h2.finish());
+
+        assert!(format!("{:?}", a).starts_with("[0, 1, 2"));
+    }
+
+    /// Lists with NaN are not equal, even if they share their nodes.
+    #[test]
+    fn test_arclist_nan() {
+        let a: ArcList<f64> = vec![1.0, f64::NAN].into_iter().collect();
+        assert!(a != a.clone());
+    }
 }
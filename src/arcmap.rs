@@ -0,0 +1,442 @@
+use std::sync::Arc;
+use std::borrow::Borrow;
+use std::collections::hash_map::RandomState;
+use std::fmt;
+use std::hash::{BuildHasher, Hash};
+use std::iter::FromIterator;
+use std::mem;
+use std::slice;
+
+// `ArcMap` is a hash array mapped trie. Every branch consumes `BITS` bits
+// of the hash and only stores the children, that exist. A bitmap tells
+// which ones these are, so a child is found by counting the bits below
+// its index.
+//
+// Leaves store all entries with the same full hash. Leaves with different
+// hashes are pushed down into new branches until their hashes differ in
+// the bits of a level. The depth is therefore bounded by the hash width.
+//
+// Nodes are immutable and shared between versions. Changes copy the path
+// from the root to the changed leaf.
+
+/// Number of hash bits used per level.
+const BITS: u32 = 5;
+const MASK: u64 = (1 << BITS) - 1;
+
+enum Node<K, V> {
+    Branch(u32, Vec<NodeRef<K, V>>),
+    Leaf(u64, Vec<(K, V)>),
+}
+
+type NodeRef<K, V> = Arc<Node<K, V>>;
+
+/// `ArcMap` is a persistent hash map with cheap `Clone`.
+///
+/// `insert` and `remove` copy `O(log n)` nodes and share the rest with the
+/// previous version. Storing an `ArcMap` in a single `TVar` therefore makes
+/// writes cheap, while reads don't have to look at more than one variable.
+///
+///
+/// # Example
+///
+/// ```
+/// extern crate stm;
+/// extern crate stm_datastructures;
+///
+/// use stm::*;
+/// use stm_datastructures::ArcMap;
+///
+/// fn main() {
+///     let var = TVar::new(ArcMap::new());
+///     let x = atomically(|tx| {
+///         var.modify(tx, |mut map| {
+///             map.insert("a", 1);
+///             map
+///         })?;
+///         Ok(var.read(tx)?.get("a").cloned())
+///     });
+///     assert_eq!(x, Some(1));
+/// }
+/// ```
+pub struct ArcMap<K, V, S = RandomState> {
+    root: Option<NodeRef<K, V>>,
+    len: usize,
+    hasher: S,
+}
+
+impl<K, V, S: Clone> Clone for ArcMap<K, V, S> {
+    fn clone(&self) -> Self {
+        ArcMap {
+            root: self.root.clone(),
+            len: self.len,
+            hasher: self.hasher.clone(),
+        }
+    }
+}
+
+impl<K, V, S: Default> Default for ArcMap<K, V, S> {
+    fn default() -> Self {
+        ArcMap::with_hasher(S::default())
+    }
+}
+
+impl<K, V> ArcMap<K, V> {
+    /// Create a new, empty map.
+    pub fn new() -> Self {
+        ArcMap::with_hasher(RandomState::new())
+    }
+}
+
+impl<K, V, S> ArcMap<K, V, S> {
+    /// Create a new, empty map, that uses `hasher` to hash the keys.
+    pub fn with_hasher(hasher: S) -> Self {
+        ArcMap {
+            root: None,
+            len: 0,
+            hasher,
+        }
+    }
+
+    /// Return the number of entries.
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Check if the map is empty.
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Iterate over all entries in arbitrary order.
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        let stack = match self.root {
+            Some(ref root) => vec![slice::from_ref(root).iter()],
+            None => Vec::new(),
+        };
+        Iter {
+            stack,
+            leaf: [].iter(),
+        }
+    }
+}
+
+impl<K, V, S> ArcMap<K, V, S>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+    S: BuildHasher,
+{
+    /// Return a reference to the value of `key`.
+    pub fn get<Q>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let hash = self.hasher.hash_one(key);
+        let mut node = self.root.as_ref()?;
+        let mut shift = 0;
+        loop {
+            match **node {
+                Node::Branch(bitmap, ref children) => {
+                    let bit = 1 << ((hash >> shift) & MASK);
+                    if bitmap & bit == 0 {
+                        return None;
+                    }
+                    node = &children[index(bitmap, bit)];
+                    shift += BITS;
+                }
+                Node::Leaf(h, ref entries) => {
+                    if h != hash {
+                        return None;
+                    }
+                    return entries.iter().find(|e| e.0.borrow() == key).map(|e| &e.1);
+                }
+            }
+        }
+    }
+
+    /// Check if the map contains `key`.
+    pub fn contains_key<Q>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        self.get(key).is_some()
+    }
+
+    /// Insert a new entry and return the old value of `key`.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        let hash = self.hasher.hash_one(&key);
+        let (root, old) = match self.root {
+            Some(ref root) => insert(root, 0, hash, key, value),
+            None => (Arc::new(Node::Leaf(hash, vec![(key, value)])), None),
+        };
+        self.root = Some(root);
+        if old.is_none() {
+            self.len += 1;
+        }
+        old
+    }
+
+    /// Remove `key` and return its value.
+    pub fn remove<Q>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+        Q: Hash + Eq + ?Sized,
+    {
+        let hash = self.hasher.hash_one(key);
+        let (root, old) = remove(self.root.as_ref()?, 0, hash, key)?;
+        self.root = root;
+        self.len -= 1;
+        Some(old)
+    }
+}
+
+/// Return the position of the child `bit` in the children of a branch.
+fn index(bitmap: u32, bit: u32) -> usize {
+    (bitmap & (bit - 1)).count_ones() as usize
+}
+
+fn insert<K: Eq + Clone, V: Clone>(
+    node: &NodeRef<K, V>,
+    shift: u32,
+    hash: u64,
+    key: K,
+    value: V,
+) -> (NodeRef<K, V>, Option<V>) {
+    match **node {
+        Node::Branch(bitmap, ref children) => {
+            let bit = 1 << ((hash >> shift) & MASK);
+            let i = index(bitmap, bit);
+            let mut children = children.clone();
+            if bitmap & bit == 0 {
+                children.insert(i, Arc::new(Node::Leaf(hash, vec![(key, value)])));
+                (Arc::new(Node::Branch(bitmap | bit, children)), None)
+            } else {
+                let (child, old) = insert(&children[i], shift + BITS, hash, key, value);
+                children[i] = child;
+                (Arc::new(Node::Branch(bitmap, children)), old)
+            }
+        }
+        Node::Leaf(h, ref entries) if h == hash => {
+            let mut entries = entries.clone();
+            let old = match entries.iter_mut().find(|e| e.0 == key) {
+                Some(e) => Some(mem::replace(&mut e.1, value)),
+                None => {
+                    entries.push((key, value));
+                    None
+                }
+            };
+            (Arc::new(Node::Leaf(hash, entries)), old)
+        }
+        Node::Leaf(h, _) => {
+            // Move the leaf one level down and retry there.
+            let bit = 1 << ((h >> shift) & MASK);
+            let branch = Arc::new(Node::Branch(bit, vec![node.clone()]));
+            insert(&branch, shift, hash, key, value)
+        }
+    }
+}
+
+/// Return the replacement for `node` without `key` and the removed value.
+///
+/// Return `None` if `key` is not in the map.
+fn remove<K, V, Q>(
+    node: &NodeRef<K, V>,
+    shift: u32,
+    hash: u64,
+    key: &Q,
+) -> Option<(Option<NodeRef<K, V>>, V)>
+where
+    K: Borrow<Q> + Clone,
+    V: Clone,
+    Q: Eq + ?Sized,
+{
+    match **node {
+        Node::Branch(bitmap, ref children) => {
+            let bit = 1 << ((hash >> shift) & MASK);
+            if bitmap & bit == 0 {
+                return None;
+            }
+            let i = index(bitmap, bit);
+            let (child, old) = remove(&children[i], shift + BITS, hash, key)?;
+            let mut children = children.clone();
+            let bitmap = match child {
+                Some(child) => {
+                    children[i] = child;
+                    bitmap
+                }
+                None => {
+                    children.remove(i);
+                    bitmap & !bit
+                }
+            };
+            let node = match children.len() {
+                0 => None,
+                // A single leaf doesn't need a branch.
+                1 if is_leaf(&children[0]) => Some(children.pop().unwrap()),
+                _ => Some(Arc::new(Node::Branch(bitmap, children))),
+            };
+            Some((node, old))
+        }
+        Node::Leaf(h, ref entries) => {
+            if h != hash {
+                return None;
+            }
+            let pos = entries.iter().position(|e| e.0.borrow() == key)?;
+            let mut entries = entries.clone();
+            let (_, old) = entries.remove(pos);
+            let node = if entries.is_empty() {
+                None
+            } else {
+                Some(Arc::new(Node::Leaf(h, entries)))
+            };
+            Some((node, old))
+        }
+    }
+}
+
+fn is_leaf<K, V>(node: &Node<K, V>) -> bool {
+    match *node {
+        Node::Leaf(..) => true,
+        Node::Branch(..) => false,
+    }
+}
+
+/// Iterator over the entries of an `ArcMap`.
+///
+/// Created by `ArcMap::iter`.
+pub struct Iter<'a, K: 'a, V: 'a> {
+    /// Remaining children of all branches on the current path.
+    stack: Vec<slice::Iter<'a, NodeRef<K, V>>>,
+    leaf: slice::Iter<'a, (K, V)>,
+}
+
+impl<'a, K, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((k, v)) = self.leaf.next() {
+                return Some((k, v));
+            }
+            let node = match self.stack.last_mut()?.next() {
+                Some(node) => node,
+                None => {
+                    self.stack.pop();
+                    continue;
+                }
+            };
+            match **node {
+                Node::Branch(_, ref children) => self.stack.push(children.iter()),
+                Node::Leaf(_, ref entries) => self.leaf = entries.iter(),
+            }
+        }
+    }
+}
+
+impl<'a, K, V, S> IntoIterator for &'a ArcMap<K, V, S> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Iter<'a, K, V> {
+        self.iter()
+    }
+}
+
+impl<K, V, S> FromIterator<(K, V)> for ArcMap<K, V, S>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+    S: BuildHasher + Default,
+{
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = ArcMap::default();
+        map.extend(iter);
+        map
+    }
+}
+
+impl<K, V, S> Extend<(K, V)> for ArcMap<K, V, S>
+where
+    K: Hash + Eq + Clone,
+    V: Clone,
+    S: BuildHasher,
+{
+    fn extend<I: IntoIterator<Item = (K, V)>>(&mut self, iter: I) {
+        for (k, v) in iter {
+            self.insert(k, v);
+        }
+    }
+}
+
+impl<K: fmt::Debug, V: fmt::Debug, S> fmt::Debug for ArcMap<K, V, S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_map().entries(self.iter()).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::HashMap;
+    use std::hash::BuildHasherDefault;
+    use std::hash::Hasher;
+
+    /// Hashes everything to the same value.
+    #[derive(Default)]
+    struct Collide;
+
+    impl Hasher for Collide {
+        fn finish(&self) -> u64 {
+            7
+        }
+        fn write(&mut self, _: &[u8]) {}
+    }
+
+    /// Compare with a `HashMap` and check, that old versions stay unchanged.
+    #[test]
+    fn test_arcmap_ops() {
+        let mut map = ArcMap::new();
+        let mut expected = HashMap::new();
+        for i in 0..10000 {
+            assert_eq!(expected.insert(i, i * 2), map.insert(i, i * 2));
+        }
+        let old = map.clone();
+        for i in (0..10000).filter(|i| i % 3 == 0) {
+            assert_eq!(expected.remove(&i), map.remove(&i));
+        }
+        assert_eq!(None, map.remove(&3));
+        assert_eq!(Some(4), map.insert(2, 5));
+        expected.insert(2, 5);
+
+        assert_eq!(expected.len(), map.len());
+        assert_eq!(expected, map.iter().map(|(&k, &v)| (k, v)).collect());
+        assert_eq!(Some(&5), map.get(&2));
+        assert!(!map.contains_key(&3));
+
+        assert_eq!(10000, old.len());
+        assert_eq!(Some(&6), old.get(&3));
+        assert_eq!(10000, old.iter().count());
+
+        for i in 0..10000 {
+            map.remove(&i);
+        }
+        assert!(map.is_empty());
+        assert!(map.root.is_none());
+    }
+
+    /// Keys with equal hashes share a leaf.
+    #[test]
+    fn test_arcmap_collisions() {
+        let mut map: ArcMap<i32, i32, BuildHasherDefault<Collide>> = ArcMap::default();
+        map.extend((0..10).map(|i| (i, i)));
+        assert_eq!(10, map.len());
+        assert_eq!(Some(&4), map.get(&4));
+        assert_eq!(Some(4), map.remove(&4));
+        assert_eq!(None, map.get(&4));
+        let mut keys: Vec<_> = map.iter().map(|(&k, _)| k).collect();
+        keys.sort();
+        assert_eq!(vec![0, 1, 2, 3, 5, 6, 7, 8, 9], keys);
+    }
+}
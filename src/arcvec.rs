@@ -0,0 +1,415 @@
+use std::sync::Arc;
+use std::fmt;
+use std::iter::FromIterator;
+use std::mem;
+
+// `ArcVec` is a persistent AVL tree, where the in-order traversal gives the
+// elements. Every node stores the number of elements in its subtree, so
+// an index can be found by walking down a single path.
+//
+// All operations are based on `join`, which combines two trees and an
+// element between them while keeping the balance. `split_at` and `concat`
+// are composed of `O(log n)` joins with shrinking height differences and
+// therefore also take logarithmic time.
+//
+// Nodes are shared between versions. Changing a vector copies the nodes on
+// the path to the change, all other nodes are reused. Because the tree is
+// balanced, recursion is bounded by its height, also when dropping it.
+
+/// `ArcVec` is a persistent vector with cheap `Clone`.
+///
+/// Indexing, updates at any position, `push`, `pop`, `split_at` and
+/// `concat` take logarithmic time. Versions share most of their nodes,
+/// so storing an `ArcVec` in a `TVar` only copies the changed path
+/// on every write.
+///
+///
+/// # Example
+///
+/// ```
+/// extern crate stm;
+/// extern crate stm_datastructures;
+///
+/// use stm::*;
+/// use stm_datastructures::ArcVec;
+///
+/// fn main() {
+///     let var = TVar::new((0..100).collect::<ArcVec<i32>>());
+///     let x = atomically(|tx| {
+///         let mut vec = var.read(tx)?;
+///         vec.set(10, 42);
+///         vec.push(100);
+///         var.write(tx, vec)?;
+///         Ok(var.read(tx)?.get(10).cloned())
+///     });
+///     assert_eq!(x, Some(42));
+/// }
+/// ```
+pub struct ArcVec<T> {
+    root: Option<Arc<Node<T>>>,
+}
+
+struct Node<T> {
+    value: T,
+    left: ArcVec<T>,
+    right: ArcVec<T>,
+    len: usize,
+    height: u8,
+}
+
+impl<T> Clone for ArcVec<T> {
+    fn clone(&self) -> Self {
+        ArcVec { root: self.root.clone() }
+    }
+}
+
+impl<T> Default for ArcVec<T> {
+    fn default() -> Self {
+        ArcVec::new()
+    }
+}
+
+impl<T> ArcVec<T> {
+    /// Create a new, empty vector.
+    pub fn new() -> Self {
+        ArcVec { root: None }
+    }
+
+    /// Return the number of elements.
+    pub fn len(&self) -> usize {
+        self.root.as_ref().map_or(0, |n| n.len)
+    }
+
+    /// Check if the vector is empty.
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    /// Return a reference to the element at `index`.
+    pub fn get(&self, mut index: usize) -> Option<&T> {
+        let mut vec = self;
+        while let Some(ref node) = vec.root {
+            let left = node.left.len();
+            if index < left {
+                vec = &node.left;
+            } else if index == left {
+                return Some(&node.value);
+            } else {
+                index -= left + 1;
+                vec = &node.right;
+            }
+        }
+        None
+    }
+
+    /// Return a reference to the first element.
+    pub fn first(&self) -> Option<&T> {
+        self.get(0)
+    }
+
+    /// Return a reference to the last element.
+    pub fn last(&self) -> Option<&T> {
+        self.len().checked_sub(1).and_then(|i| self.get(i))
+    }
+
+    /// Iterate over references to the elements.
+    pub fn iter(&self) -> Iter<'_, T> {
+        let mut iter = Iter { stack: Vec::new() };
+        iter.push_left(self);
+        iter
+    }
+
+    fn height(&self) -> u8 {
+        self.root.as_ref().map_or(0, |n| n.height)
+    }
+
+    /// Create a node without balancing.
+    fn node(left: Self, value: T, right: Self) -> Self {
+        let node = Node {
+            len: left.len() + right.len() + 1,
+            height: left.height().max(right.height()) + 1,
+            value,
+            left,
+            right,
+        };
+        ArcVec { root: Some(Arc::new(node)) }
+    }
+}
+
+impl<T: Clone> ArcVec<T> {
+    /// Add an element to the end.
+    pub fn push(&mut self, value: T) {
+        *self = ArcVec::join(self.take(), value, ArcVec::new());
+    }
+
+    /// Remove the last element.
+    pub fn pop(&mut self) -> Option<T> {
+        self.pop_last().map(|(rest, x)| {
+            *self = rest;
+            x
+        })
+    }
+
+    /// Replace the element at `index` and return the old one.
+    ///
+    /// Return `None` and drop `value`, if `index` is out of bounds.
+    pub fn set(&mut self, index: usize, value: T) -> Option<T> {
+        if index >= self.len() {
+            return None;
+        }
+        let mut old = None;
+        *self = self.replaced(index, value, &mut old);
+        old
+    }
+
+    /// Split the vector into the first `index` elements and the rest.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `index > len`.
+    pub fn split_at(&self, index: usize) -> (Self, Self) {
+        assert!(index <= self.len(), "split index out of bounds");
+        self.split(index)
+    }
+
+    /// Return a vector with the elements of `self` followed by `other`.
+    pub fn concat(&self, other: &Self) -> Self {
+        match self.pop_last() {
+            Some((init, last)) => ArcVec::join(init, last, other.clone()),
+            None => other.clone(),
+        }
+    }
+
+    fn take(&mut self) -> Self {
+        ArcVec { root: self.root.take() }
+    }
+
+    /// Return the parts of the root node.
+    ///
+    /// Must only be called on non-empty vectors.
+    fn parts(&self) -> (Self, T, Self) {
+        let node = self.root.as_ref().expect("parts of an empty vector");
+        (node.left.clone(), node.value.clone(), node.right.clone())
+    }
+
+    /// Create a node and rotate, if the heights differ by two.
+    fn balance(left: Self, value: T, right: Self) -> Self {
+        let (hl, hr) = (left.height(), right.height());
+        if hl > hr + 1 {
+            let (ll, lx, lr) = left.parts();
+            if ll.height() >= lr.height() {
+                ArcVec::node(ll, lx, ArcVec::node(lr, value, right))
+            } else {
+                let (lrl, lrx, lrr) = lr.parts();
+                ArcVec::node(ArcVec::node(ll, lx, lrl), lrx, ArcVec::node(lrr, value, right))
+            }
+        } else if hr > hl + 1 {
+            let (rl, rx, rr) = right.parts();
+            if rr.height() >= rl.height() {
+                ArcVec::node(ArcVec::node(left, value, rl), rx, rr)
+            } else {
+                let (rll, rlx, rlr) = rl.parts();
+                ArcVec::node(ArcVec::node(left, value, rll), rlx, ArcVec::node(rlr, rx, rr))
+            }
+        } else {
+            ArcVec::node(left, value, right)
+        }
+    }
+
+    /// Combine `left`, `value` and `right` into a balanced tree of any heights.
+    fn join(left: Self, value: T, right: Self) -> Self {
+        let (hl, hr) = (left.height(), right.height());
+        if hl > hr + 1 {
+            let (ll, lx, lr) = left.parts();
+            ArcVec::balance(ll, lx, ArcVec::join(lr, value, right))
+        } else if hr > hl + 1 {
+            let (rl, rx, rr) = right.parts();
+            ArcVec::balance(ArcVec::join(left, value, rl), rx, rr)
+        } else {
+            ArcVec::node(left, value, right)
+        }
+    }
+
+    fn split(&self, index: usize) -> (Self, Self) {
+        let node = match self.root {
+            Some(ref node) => node,
+            None => return (ArcVec::new(), ArcVec::new()),
+        };
+        let left = node.left.len();
+        if index <= left {
+            let (a, b) = node.left.split(index);
+            (a, ArcVec::join(b, node.value.clone(), node.right.clone()))
+        } else {
+            let (a, b) = node.right.split(index - left - 1);
+            (ArcVec::join(node.left.clone(), node.value.clone(), a), b)
+        }
+    }
+
+    /// Return the vector without its last element and the element.
+    fn pop_last(&self) -> Option<(Self, T)> {
+        self.root.as_ref().map(|node| match node.right.pop_last() {
+            Some((right, x)) => (ArcVec::balance(node.left.clone(), node.value.clone(), right), x),
+            None => (node.left.clone(), node.value.clone()),
+        })
+    }
+
+    /// Copy the path to `index` with `value` at its end.
+    fn replaced(&self, index: usize, value: T, old: &mut Option<T>) -> Self {
+        let node = self.root.as_ref().expect("index out of bounds");
+        let left = node.left.len();
+        let mut new = Node {
+            value: node.value.clone(),
+            left: node.left.clone(),
+            right: node.right.clone(),
+            len: node.len,
+            height: node.height,
+        };
+        if index < left {
+            new.left = node.left.replaced(index, value, old);
+        } else if index == left {
+            *old = Some(mem::replace(&mut new.value, value));
+        } else {
+            new.right = node.right.replaced(index - left - 1, value, old);
+        }
+        ArcVec { root: Some(Arc::new(new)) }
+    }
+}
+
+/// Iterator over references to the elements of an `ArcVec`.
+///
+/// Created by `ArcVec::iter`.
+pub struct Iter<'a, T: 'a> {
+    /// Nodes, whose value and right subtree are still pending.
+    stack: Vec<&'a Node<T>>,
+}
+
+impl<'a, T> Iter<'a, T> {
+    fn push_left(&mut self, mut vec: &'a ArcVec<T>) {
+        while let Some(ref node) = vec.root {
+            self.stack.push(node);
+            vec = &node.left;
+        }
+    }
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.stack.pop().map(|node| {
+            self.push_left(&node.right);
+            &node.value
+        })
+    }
+}
+
+impl<'a, T> IntoIterator for &'a ArcVec<T> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Iter<'a, T> {
+        self.iter()
+    }
+}
+
+impl<T: Clone> FromIterator<T> for ArcVec<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut vec = ArcVec::new();
+        vec.extend(iter);
+        vec
+    }
+}
+
+impl<T: Clone> Extend<T> for ArcVec<T> {
+    /// Push all elements to the end.
+    fn extend<I: IntoIterator<Item = T>>(&mut self, iter: I) {
+        for x in iter {
+            self.push(x);
+        }
+    }
+}
+
+impl<T: fmt::Debug> fmt::Debug for ArcVec<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_list().entries(self.iter()).finish()
+    }
+}
+
+impl<T: PartialEq> PartialEq for ArcVec<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.len() == other.len() && self.iter().eq(other.iter())
+    }
+}
+
+impl<T: Eq> Eq for ArcVec<T> {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Height of an AVL tree is at most about `1.44 * log2(n)`.
+    fn check_balanced<T>(vec: &ArcVec<T>) {
+        let n = vec.len() as f64 + 2.0;
+        assert!((vec.height() as f64) <= 1.45 * n.log2());
+    }
+
+    /// Compare all operations with a `Vec`.
+    #[test]
+    fn test_arcvec_ops() {
+        let mut vec: ArcVec<usize> = (0..100).collect();
+        let mut expected: Vec<usize> = (0..100).collect();
+        let old = vec.clone();
+
+        assert_eq!(Some(50), vec.set(50, 1000));
+        expected[50] = 1000;
+        assert_eq!(None, vec.set(100, 0));
+        assert_eq!(Some(99), vec.pop());
+        expected.pop();
+        vec.push(7);
+        expected.push(7);
+        assert_eq!(expected, vec.iter().cloned().collect::<Vec<_>>());
+        assert_eq!(Some(&1000), vec.get(50));
+        assert_eq!(None, vec.get(100));
+        assert_eq!(Some(&7), vec.last());
+
+        // The old version is unchanged.
+        assert_eq!((0..100).collect::<ArcVec<_>>(), old);
+
+        for i in 0..101 {
+            let (a, b) = vec.split_at(i);
+            check_balanced(&a);
+            check_balanced(&b);
+            assert_eq!(&expected[..i], &a.iter().cloned().collect::<Vec<_>>()[..]);
+            assert_eq!(&expected[i..], &b.iter().cloned().collect::<Vec<_>>()[..]);
+            assert_eq!(vec, a.concat(&b));
+        }
+    }
+
+    /// Concatenate vectors of very different sizes.
+    #[test]
+    fn test_arcvec_concat() {
+        let small: ArcVec<i32> = (0..3).collect();
+        let big: ArcVec<i32> = (3..10000).collect();
+        let all = small.concat(&big);
+        check_balanced(&all);
+        assert_eq!((0..10000).collect::<ArcVec<_>>(), all);
+        let all = all.concat(&small);
+        check_balanced(&all);
+        assert_eq!(10003, all.len());
+        assert_eq!(Some(&2), all.last());
+        assert_eq!(ArcVec::<i32>::new(), ArcVec::new().concat(&ArcVec::new()));
+    }
+
+    /// Long vectors stay balanced, so nothing overflows the stack.
+    #[test]
+    fn test_long_arcvec() {
+        let mut vec: ArcVec<i32> = (0..100000).collect();
+        check_balanced(&vec);
+        for _ in 0..50000 {
+            vec.pop();
+        }
+        check_balanced(&vec);
+        assert_eq!(Some(&49999), vec.last());
+        assert_eq!(50000, vec.iter().fold(0, |acc, _| acc + 1));
+    }
+}
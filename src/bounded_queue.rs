@@ -1,6 +1,13 @@
 use stm::*;
 use std::any::Any;
+use std::time::Duration;
 use super::Queue;
+use super::queue::Closed;
+use super::timeout::Timeout;
+#[cfg(feature = "async")]
+use std::future::Future;
+#[cfg(feature = "async")]
+use super::future::atomically_async;
 
 /// `Queue` is a threadsafe FIFO queue, that uses software transactional memory.
 ///
@@ -19,10 +26,10 @@ use super::Queue;
 /// fn main() {
 /// let queue = BoundedQueue::new(10);
 /// let x = atomically(|tx| {
-///     queue.push(tx, 42)?;
+///     queue.push(tx, 42)?.unwrap();
 ///     queue.pop(tx)
 /// });
-/// assert_eq!(x, 42);
+/// assert_eq!(x, Ok(42));
 /// }
 /// ```
 #[derive(Clone)]
@@ -30,9 +37,18 @@ pub struct BoundedQueue<T> {
     /// Internally use a normal queue.
     queue: Queue<T>,
 
+    /// The maximal number of elements.
+    capacity: usize,
+
     /// `cap` stores the number of elements, that may still
     /// fit into this queue.
     cap: TVar<usize>,
+
+    /// Tickets of threads blocked in `push_fair` in arrival order.
+    ///
+    /// Only used in fair mode. Free space is handed to the oldest
+    /// ticket instead of increasing `cap`, just like in `FairSemaphore`.
+    waiters: Option<Queue<TVar<bool>>>,
 }
 
 
@@ -42,12 +58,75 @@ impl<T: Any + Sync + Clone + Send> BoundedQueue<T> {
     pub fn new(capacity: usize) -> BoundedQueue<T> {
         BoundedQueue {
             queue: Queue::new(),
+            capacity,
             cap: TVar::new(capacity),
+            waiters: None,
+        }
+    }
+
+    /// Create new `BoundedQueue` in fair mode.
+    ///
+    /// In fair mode threads blocked in `push_fair` get space in the order
+    /// they arrived. Transactional pushes never take space away from them,
+    /// but may wait for a long time while the queue is congested.
+    pub fn new_fair(capacity: usize) -> BoundedQueue<T> {
+        BoundedQueue {
+            waiters: Some(Queue::new()),
+            ..BoundedQueue::new(capacity)
+        }
+    }
+
+    /// Add a new element to the queue and block until there is space.
+    ///
+    /// In fair mode waiting threads are served in FIFO order. This runs its
+    /// own transactions and therefore must not be called inside of a transaction.
+    ///
+    /// Fail if the queue is closed, even while waiting.
+    pub fn push_fair(&self, value: T) -> Result<(), Closed> {
+        let waiters = match self.waiters {
+            Some(ref w) => w,
+            None => return atomically(|tx| self.push(tx, value.clone())),
+        };
+        let ticket = atomically(|tx| {
+            if self.queue.is_closed(tx)? {
+                return Ok(Err(Closed));
+            }
+            let cap = self.cap.read(tx)?;
+            if cap > 0 {
+                self.cap.write(tx, cap - 1)?;
+                return Ok(self.queue.push(tx, value.clone())?.map(|_| None));
+            }
+            let ticket = TVar::new(false);
+            waiters
+                .push(tx, ticket.clone())?
+                .expect("ticket queue is never closed");
+            Ok(Ok(Some(ticket)))
+        })?;
+        match ticket {
+            // The space has already been reserved by `free`.
+            Some(ticket) => atomically(|tx| {
+                if !ticket.read(tx)? {
+                    guard(self.queue.is_closed(tx)?)?;
+                    return Ok(Err(Closed));
+                }
+                if self.queue.is_closed(tx)? {
+                    // Give the reserved space back.
+                    self.free(tx, 1)?;
+                    return Ok(Err(Closed));
+                }
+                self.queue.push(tx, value.clone())
+            }),
+            None => Ok(()),
         }
     }
 
     /// Add a new element to the queue.
-    pub fn push(&self, tx: &mut Transaction, val: T) -> StmResult<()> {
+    ///
+    /// Retry while the queue is full and fail if it is closed.
+    pub fn push(&self, tx: &mut Transaction, val: T) -> StmResult<Result<(), Closed>> {
+        if self.queue.is_closed(tx)? {
+            return Ok(Err(Closed));
+        }
         let cap = self.cap.read(tx)?;
         guard(cap > 0)?;
         self.cap.write(tx, cap - 1)?;
@@ -70,7 +149,9 @@ impl<T: Any + Sync + Clone + Send> BoundedQueue<T> {
     }
 
     /// Return the first element without removing it.
-    pub fn peek(&self, tx: &mut Transaction) -> StmResult<T> {
+    ///
+    /// Retry if the queue is empty and fail if it is also closed.
+    pub fn peek(&self, tx: &mut Transaction) -> StmResult<Result<T, Closed>> {
         self.queue.peek(tx)
     }
 
@@ -78,15 +159,175 @@ impl<T: Any + Sync + Clone + Send> BoundedQueue<T> {
     pub fn try_pop(&self, tx: &mut Transaction) -> StmResult<Option<T>> {
         let v = self.queue.try_pop(tx)?;
         if v.is_some() {
-            self.cap.modify(tx, |x| x + 1)?;
+            self.free(tx, 1)?;
         }
         Ok(v)
     }
 
     /// Remove an element from the queue.
-    pub fn pop(&self, tx: &mut Transaction) -> StmResult<T> {
-        self.cap.modify(tx, |x| x + 1)?;
-        self.queue.pop(tx)
+    ///
+    /// Retry if the queue is empty. Once the queue is closed and all
+    /// elements have been removed, fail instead of retrying.
+    pub fn pop(&self, tx: &mut Transaction) -> StmResult<Result<T, Closed>> {
+        let v = self.queue.pop(tx)?;
+        if v.is_ok() {
+            self.free(tx, 1)?;
+        }
+        Ok(v)
+    }
+
+    /// Add all elements of `iter` to the queue.
+    ///
+    /// Retry until there is enough space for all of them and fail if the
+    /// queue is closed.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `iter` yields more elements than the capacity, because
+    /// they could never fit into the queue.
+    pub fn push_all<I>(&self, tx: &mut Transaction, iter: I) -> StmResult<Result<(), Closed>>
+    where
+        I: IntoIterator<Item = T>,
+    {
+        let values: Vec<T> = iter.into_iter().collect();
+        assert!(values.len() <= self.capacity, "push_all exceeds the capacity of the queue");
+        if self.queue.is_closed(tx)? {
+            return Ok(Err(Closed));
+        }
+        let cap = self.cap.read(tx)?;
+        guard(cap >= values.len())?;
+        self.cap.write(tx, cap - values.len())?;
+        self.queue.push_all(tx, values)
+    }
+
+    /// Remove up to `n` elements from the queue.
+    pub fn pop_up_to(&self, tx: &mut Transaction, n: usize) -> StmResult<Vec<T>> {
+        let v = self.queue.pop_up_to(tx, n)?;
+        self.free(tx, v.len())?;
+        Ok(v)
+    }
+
+    /// Remove exactly `n` elements from the queue or retry if there are less.
+    ///
+    /// Fail without removing anything, if the queue is closed and
+    /// can therefore never contain `n` elements.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` is larger than the capacity, because the queue
+    /// could never contain `n` elements.
+    pub fn pop_n(&self, tx: &mut Transaction, n: usize) -> StmResult<Result<Vec<T>, Closed>> {
+        assert!(n <= self.capacity, "pop_n exceeds the capacity of the queue");
+        let v = self.queue.pop_n(tx, n)?;
+        if v.is_ok() {
+            self.free(tx, n)?;
+        }
+        Ok(v)
+    }
+
+    /// Remove all elements from the queue.
+    pub fn drain(&self, tx: &mut Transaction) -> StmResult<Vec<T>> {
+        let v = self.queue.drain(tx)?;
+        self.free(tx, v.len())?;
+        Ok(v)
+    }
+
+    /// Count the elements in the queue.
+    pub fn len(&self, tx: &mut Transaction) -> StmResult<usize> {
+        self.queue.len(tx)
+    }
+
+    /// Close the queue.
+    ///
+    /// Further pushes fail. Remaining elements can still be popped, after
+    /// that `pop` fails instead of blocking. Threads blocked in `push_fair`
+    /// fail as well.
+    pub fn close(&self, tx: &mut Transaction) -> StmResult<()> {
+        if let Some(ref waiters) = self.waiters {
+            // The waiting threads see the closed queue and give up, so
+            // their tickets must not receive any more space.
+            waiters.drain(tx)?;
+        }
+        self.queue.close(tx)
+    }
+
+    /// Check if the queue has been closed.
+    pub fn is_closed(&self, tx: &mut Transaction) -> StmResult<bool> {
+        self.queue.is_closed(tx)
+    }
+
+    /// Return the maximal number of elements.
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Return the number of elements, that still fit into the queue.
+    pub fn remaining(&self, tx: &mut Transaction) -> StmResult<usize> {
+        self.cap.read(tx)
+    }
+
+    /// Add a value to the end of the queue in its own transaction.
+    ///
+    /// Block while the queue is full. In fair mode this is the same as
+    /// `push_fair`. Like all `_now` and `_blocking` methods, this must not
+    /// be called inside of a transaction.
+    pub fn push_now(&self, value: T) -> Result<(), Closed> {
+        self.push_fair(value)
+    }
+
+    /// Remove the first element in its own transaction.
+    ///
+    /// Block while the queue is empty and fail once it is closed.
+    pub fn pop_blocking(&self) -> Result<T, Closed> {
+        atomically(|tx| self.pop(tx))
+    }
+
+    /// Remove the first element in its own transaction without blocking.
+    pub fn try_pop_now(&self) -> Option<T> {
+        atomically(|tx| self.try_pop(tx))
+    }
+
+    /// Remove the first element in its own transaction.
+    ///
+    /// Return `Ok(None)` if no element arrived within `timeout`. Like
+    /// `Queue::pop_timeout` this does not start a thread.
+    pub fn pop_timeout(&self, timeout: Duration) -> Result<Option<T>, Closed> {
+        let timeout = Timeout::after(timeout);
+        atomically(|tx| {
+            tx.or(
+                |tx| Ok(self.pop(tx)?.map(Some)),
+                |tx| timeout.wait(tx).map(|_| Ok(None)),
+            )
+        })
+    }
+
+    /// Add a value to the end of the queue without blocking the thread.
+    ///
+    /// The returned future completes once there is space or the queue is
+    /// closed. It does not take part in the fair mode.
+    #[cfg(feature = "async")]
+    pub fn push_async(&self, value: T) -> impl Future<Output = Result<(), Closed>> {
+        let queue = self.clone();
+        atomically_async(move |tx| queue.push(tx, value.clone()))
+    }
+
+    /// Make space for `n` more elements.
+    ///
+    /// In fair mode the space goes to waiting threads first.
+    fn free(&self, tx: &mut Transaction, mut n: usize) -> StmResult<()> {
+        if let Some(ref waiters) = self.waiters {
+            while n > 0 {
+                match waiters.try_pop(tx)? {
+                    Some(ticket) => ticket.write(tx, true)?,
+                    None => break,
+                }
+                n -= 1;
+            }
+        }
+        if n > 0 {
+            self.cap.modify(tx, |x| x + n)?;
+        }
+        Ok(())
     }
 
     /// Check if a queue is empty.
@@ -112,10 +353,10 @@ mod tests {
     fn bqueue_push_pop() {
         let queue = BoundedQueue::new(1);
         let x = atomically(|tx| {
-            queue.push(tx, 42)?;
+            queue.push(tx, 42)?.unwrap();
             queue.pop(tx)
         });
-        assert_eq!(42, x);
+        assert_eq!(Ok(42), x);
     }
 
     /// Test if push and pop operations maintain the order (FIFO).
@@ -123,12 +364,12 @@ mod tests {
     fn bqueue_order() {
         let queue = BoundedQueue::new(3);
         let x = atomically(|tx| {
-            queue.push(tx, 1)?;
-            queue.push(tx, 2)?;
-            queue.push(tx, 3)?;
-            let x1 = queue.pop(tx)?;
-            let x2 = queue.pop(tx)?;
-            let x3 = queue.pop(tx)?;
+            queue.push(tx, 1)?.unwrap();
+            queue.push(tx, 2)?.unwrap();
+            queue.push(tx, 3)?.unwrap();
+            let x1 = queue.pop(tx)?.unwrap();
+            let x2 = queue.pop(tx)?.unwrap();
+            let x3 = queue.pop(tx)?.unwrap();
             Ok((x1, x2, x3))
         });
         assert_eq!((1, 2, 3), x);
@@ -143,20 +384,183 @@ mod tests {
         let queue2 = queue.clone();
 
         atomically(|tx| {
-            queue2.push(tx, 1)?;
+            queue2.push(tx, 1)?.unwrap();
             queue2.push(tx, 2)
-        });
-        atomically(|tx| queue.push(tx, 3));
+        }).unwrap();
+        atomically(|tx| queue.push(tx, 3)).unwrap();
 
         let x = atomically(|tx| {
-            let x1 = queue.pop(tx)?;
-            let x2 = queue.pop(tx)?;
-            let x3 = queue.pop(tx)?;
+            let x1 = queue.pop(tx)?.unwrap();
+            let x2 = queue.pop(tx)?.unwrap();
+            let x3 = queue.pop(tx)?.unwrap();
             Ok((x1, x2, x3))
         });
         assert_eq!((1, 2, 3), x);
     }
 
+    /// Check the bulk operations together with the capacity.
+    #[test]
+    fn bqueue_bulk() {
+        let queue = BoundedQueue::new(5);
+        atomically(|tx| queue.push_all(tx, 0..5)).unwrap();
+        assert!(atomically(|tx| queue.is_full(tx)));
+
+        assert_eq!(Ok(vec![0, 1]), atomically(|tx| queue.pop_n(tx, 2)));
+        atomically(|tx| queue.push_all(tx, 5..7)).unwrap();
+        assert_eq!(5, atomically(|tx| queue.len(tx)));
+        assert_eq!(vec![2, 3, 4, 5], atomically(|tx| queue.pop_up_to(tx, 4)));
+        assert_eq!(vec![6], atomically(|tx| queue.drain(tx)));
+
+        // All the space is available again.
+        atomically(|tx| queue.push_all(tx, 0..5)).unwrap();
+    }
+
+    /// More elements than the capacity can never be pushed at once.
+    #[test]
+    #[should_panic(expected = "push_all exceeds the capacity")]
+    fn bqueue_push_all_too_many() {
+        let queue = BoundedQueue::new(2);
+        atomically(|tx| queue.push_all(tx, 0..3)).unwrap();
+    }
+
+    /// More elements than the capacity can never be popped at once.
+    #[test]
+    #[should_panic(expected = "pop_n exceeds the capacity")]
+    fn bqueue_pop_n_too_many() {
+        let queue = BoundedQueue::<i32>::new(2);
+        atomically(|tx| queue.pop_n(tx, 3)).unwrap();
+    }
+
+    /// Test the wrappers, that run their own transactions.
+    #[test]
+    fn bqueue_now() {
+        use std::thread;
+        use std::time::Duration;
+        let queue = BoundedQueue::new(1);
+        queue.push_now(1).unwrap();
+
+        let queue2 = queue.clone();
+        let h = thread::spawn(move || queue2.push_now(2));
+        assert_eq!(Ok(1), queue.pop_blocking());
+        h.join().unwrap().unwrap();
+
+        assert_eq!(Ok(Some(2)), queue.pop_timeout(Duration::from_secs(10)));
+        assert_eq!(None, queue.try_pop_now());
+        assert_eq!(Ok(None), queue.pop_timeout(Duration::from_millis(10)));
+        assert_eq!(1, atomically(|tx| queue.remaining(tx)));
+    }
+
+    /// Check the size queries, while the elements are split between
+    /// both internal lists.
+    #[test]
+    fn bqueue_len() {
+        let queue = BoundedQueue::new(3);
+        assert_eq!(3, queue.capacity());
+
+        atomically(|tx| {
+            queue.push(tx, 1)?.unwrap();
+            queue.push(tx, 2)?.unwrap();
+            queue.pop(tx)?.unwrap();
+            queue.push(tx, 3)
+        }).unwrap();
+        assert!(!atomically(|tx| queue.is_empty(tx)));
+        assert_eq!(2, atomically(|tx| queue.len(tx)));
+        assert_eq!(1, atomically(|tx| queue.remaining(tx)));
+
+        atomically(|tx| queue.pop_n(tx, 2)).unwrap();
+        assert!(atomically(|tx| queue.is_empty(tx)));
+        assert_eq!(3, atomically(|tx| queue.remaining(tx)));
+    }
+
+    /// In fair mode blocked producers get space in arrival order.
+    #[test]
+    fn bqueue_fair() {
+        use std::thread;
+        let queue = BoundedQueue::new_fair(1);
+        let waiters = queue.waiters.clone().unwrap();
+        queue.push_fair(0).unwrap();
+
+        let mut handles = Vec::new();
+        for i in 1..10 {
+            let queue2 = queue.clone();
+            handles.push(thread::spawn(move || queue2.push_fair(i)));
+            // Wait until the thread is queued, before starting the next one.
+            atomically(|tx| guard(waiters.len(tx)? == i));
+        }
+
+        let v: Vec<_> = (0..10).map(|_| atomically(|tx| queue.pop(tx)).unwrap()).collect();
+        assert_eq!((0..10).collect::<Vec<_>>(), v);
+        for h in handles {
+            h.join().unwrap().unwrap();
+        }
+        assert_eq!(1, atomically(|tx| queue.remaining(tx)));
+    }
+
+    /// Closing a fair queue does not lose the space reserved for
+    /// waiting producers.
+    #[test]
+    fn bqueue_fair_close() {
+        use std::thread;
+        let queue = BoundedQueue::new_fair(1);
+        let waiters = queue.waiters.clone().unwrap();
+        queue.push_fair(0).unwrap();
+
+        // Close while the producer is still waiting for its ticket.
+        let queue2 = queue.clone();
+        let h = thread::spawn(move || queue2.push_fair(1));
+        atomically(|tx| guard(waiters.len(tx)? == 1));
+        atomically(|tx| queue.close(tx));
+        assert_eq!(Err(Closed), h.join().unwrap());
+        assert_eq!(vec![0], atomically(|tx| queue.drain(tx)));
+        assert_eq!(1, atomically(|tx| queue.remaining(tx)));
+
+        // Close after the ticket has been granted, but before the
+        // producer could push.
+        let queue = BoundedQueue::new_fair(1);
+        let waiters = queue.waiters.clone().unwrap();
+        queue.push_fair(0).unwrap();
+        let queue2 = queue.clone();
+        let h = thread::spawn(move || queue2.push_fair(1));
+        atomically(|tx| guard(waiters.len(tx)? == 1));
+        atomically(|tx| {
+            queue.pop(tx)?.unwrap();
+            queue.close(tx)
+        });
+        assert_eq!(Err(Closed), h.join().unwrap());
+        assert_eq!(1, atomically(|tx| queue.remaining(tx)));
+    }
+
+    /// Closing the queue wakes up blocked producers and consumers.
+    #[test]
+    fn bqueue_close_threaded() {
+        use std::thread;
+        use std::time::Duration;
+        let full = BoundedQueue::new_fair(1);
+        let empty = BoundedQueue::<i32>::new(1);
+        full.push_fair(0).unwrap();
+
+        let full2 = full.clone();
+        let producer = thread::spawn(move || atomically(|tx| full2.push(tx, 1)));
+        let full3 = full.clone();
+        let fair_producer = thread::spawn(move || full3.push_fair(2));
+        let empty2 = empty.clone();
+        let consumer = thread::spawn(move || atomically(|tx| empty2.pop(tx)));
+
+        thread::sleep(Duration::from_millis(20));
+        atomically(|tx| {
+            full.close(tx)?;
+            empty.close(tx)
+        });
+
+        assert_eq!(Err(Closed), producer.join().unwrap());
+        assert_eq!(Err(Closed), fair_producer.join().unwrap());
+        assert_eq!(Err(Closed), consumer.join().unwrap());
+
+        // The remaining element can still be removed.
+        assert_eq!(Ok(0), atomically(|tx| full.pop(tx)));
+        assert_eq!(Err(Closed), atomically(|tx| full.pop(tx)));
+    }
+
     /// Test if the queue works with multiple concurrent threads.
     #[test]
     fn bqueue_threaded() {
@@ -173,7 +577,7 @@ mod tests {
         let mut v = atomically(|tx| {
             let mut v = Vec::new();
             for _ in 0..10 {
-                v.push(queue.pop(tx)?);
+                v.push(queue.pop(tx)?.unwrap());
             }
             Ok(v)
         });
@@ -181,9 +585,7 @@ mod tests {
         // We don't know the order, but want to check if we received everything
         // correcty.
         v.sort();
-        for i in 0..10 {
-            assert_eq!(v[i], i);
-        }
+        assert_eq!((0..10).collect::<Vec<_>>(), v);
     }
 
     /// Just like `bqueue_threaded`, but the
@@ -199,7 +601,7 @@ mod tests {
 
         for i in 0..10 {
             let queue2 = queue.clone();
-            thread::spawn(move || { atomically(|tx| queue2.push(tx, i)); });
+            thread::spawn(move || { atomically(|tx| queue2.push(tx, i)).unwrap(); });
         }
 
         // Wait for all the values.
@@ -208,14 +610,12 @@ mod tests {
         // multiple reads.
         let mut v = Vec::new();
         for _ in 0..10 {
-            v.push(atomically(|tx| queue.pop(tx)));
+            v.push(atomically(|tx| queue.pop(tx)).unwrap());
         }
 
         // We don't know the order, but want to check if we received everything
         // correcty.
         v.sort();
-        for i in 0..10 {
-            assert_eq!(v[i], i);
-        }
+        assert_eq!((0..10).collect::<Vec<_>>(), v);
     }
 }
@@ -0,0 +1,209 @@
+use stm::*;
+use std::any::Any;
+use std::sync::Arc;
+use super::raw;
+
+// The broadcast channel is a linked list of `TVar` cells just like GHC's `TChan`.
+// The writer points to the empty cell at the end (the hole). Pushing fills
+// the hole and appends a new one. Every receiver has its own pointer into
+// the list and moves it forward when popping.
+//
+// Cells, that all receivers have passed, are no longer referenced and
+// get freed. Receivers only touch the writer's cells once they reach the
+// end of the list, so slow readers don't conflict with writers.
+
+/// A cell of the list. `None` marks the hole at the end.
+type Link<T> = TVar<Option<Arc<Node<T>>>>;
+
+struct Node<T: Any + Sync + Send> {
+    value: T,
+    next: Link<T>,
+}
+
+impl<T: Any + Sync + Send> Drop for Node<T> {
+    /// Free the rest of the list without recursion, because a receiver
+    /// may lag behind by a large number of elements.
+    fn drop(&mut self) {
+        let mut next = take_node(&self.next);
+        while let Some(node) = next {
+            next = take_node(&node.next);
+        }
+    }
+}
+
+/// Take the next node out of `link` if nothing else can reach it anymore.
+fn take_node<T: Any + Sync + Send>(link: &Link<T>) -> Option<Node<T>> {
+    Arc::try_unwrap(raw::take_unique(link)??).ok()
+}
+
+/// `Broadcast` is a threadsafe channel, where every receiver gets every element.
+///
+/// Receivers are created with `subscribe` and see all elements pushed
+/// after their creation.
+///
+///
+/// # Example
+///
+/// ```
+/// extern crate stm;
+/// extern crate stm_datastructures;
+///
+/// use stm::*;
+/// use stm_datastructures::Broadcast;
+///
+/// fn main() {
+///     let chan = Broadcast::new();
+///     let rx1 = chan.subscribe();
+///     let rx2 = chan.subscribe();
+///     atomically(|tx| chan.push(tx, 42));
+///     assert_eq!(atomically(|tx| rx1.pop(tx)), 42);
+///     assert_eq!(atomically(|tx| rx2.pop(tx)), 42);
+/// }
+/// ```
+#[derive(Clone)]
+pub struct Broadcast<T: Any + Sync + Send> {
+    /// Points to the hole at the end of the list.
+    write: TVar<Link<T>>,
+}
+
+/// Receiving end of a `Broadcast`.
+///
+/// Created by `Broadcast::subscribe` or `BroadcastReceiver::duplicate`.
+pub struct BroadcastReceiver<T: Any + Sync + Send> {
+    /// Points to the next cell to read.
+    read: TVar<Link<T>>,
+}
+
+impl<T: Any + Sync + Clone + Send> Default for Broadcast<T> {
+    fn default() -> Self {
+        Broadcast::new()
+    }
+}
+
+impl<T: Any + Sync + Clone + Send> Broadcast<T> {
+    /// Create a new broadcast channel without receivers.
+    pub fn new() -> Broadcast<T> {
+        Broadcast { write: TVar::new(TVar::new(None)) }
+    }
+
+    /// Send a value to all current receivers.
+    pub fn push(&self, tx: &mut Transaction, value: T) -> StmResult<()> {
+        let hole = self.write.read(tx)?;
+        let new_hole = TVar::new(None);
+        let node = Node {
+            value,
+            next: new_hole.clone(),
+        };
+        hole.write(tx, Some(Arc::new(node)))?;
+        self.write.write(tx, new_hole)
+    }
+
+    /// Create a receiver, that gets all elements pushed from now on.
+    pub fn subscribe(&self) -> BroadcastReceiver<T> {
+        BroadcastReceiver { read: TVar::new(self.write.read_atomic()) }
+    }
+}
+
+impl<T: Any + Sync + Clone + Send> BroadcastReceiver<T> {
+    /// Remove the next element from this receiver.
+    pub fn try_pop(&self, tx: &mut Transaction) -> StmResult<Option<T>> {
+        let link = self.read.read(tx)?;
+        Ok(match link.read(tx)? {
+            Some(node) => {
+                self.read.write(tx, node.next.clone())?;
+                Some(node.value.clone())
+            }
+            None => None,
+        })
+    }
+
+    /// Remove the next element from this receiver or retry if there is none.
+    pub fn pop(&self, tx: &mut Transaction) -> StmResult<T> {
+        unwrap_or_retry(self.try_pop(tx)?)
+    }
+
+    /// Return the next element without removing it.
+    pub fn try_peek(&self, tx: &mut Transaction) -> StmResult<Option<T>> {
+        let link = self.read.read(tx)?;
+        Ok(link.read(tx)?.map(|node| node.value.clone()))
+    }
+
+    /// Return the next element without removing it or retry if there is none.
+    pub fn peek(&self, tx: &mut Transaction) -> StmResult<T> {
+        unwrap_or_retry(self.try_peek(tx)?)
+    }
+
+    /// Check if this receiver has no pending elements.
+    pub fn is_empty(&self, tx: &mut Transaction) -> StmResult<bool> {
+        let link = self.read.read(tx)?;
+        Ok(link.read(tx)?.is_none())
+    }
+
+    /// Create a new receiver at the same position.
+    ///
+    /// Both receivers get all pending and future elements independently.
+    pub fn duplicate(&self) -> BroadcastReceiver<T> {
+        BroadcastReceiver { read: TVar::new(self.read.read_atomic()) }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use stm::*;
+    use super::*;
+
+    /// Every receiver gets every element, but only those pushed after subscribing.
+    #[test]
+    fn broadcast_subscribe() {
+        let chan = Broadcast::new();
+        let rx1 = chan.subscribe();
+        atomically(|tx| chan.push(tx, 1));
+        let rx2 = chan.subscribe();
+        atomically(|tx| chan.push(tx, 2));
+        let rx3 = rx1.duplicate();
+
+        let x = atomically(|tx| Ok((rx1.pop(tx)?, rx1.pop(tx)?, rx1.try_pop(tx)?)));
+        assert_eq!((1, 2, None), x);
+        assert_eq!((2, None), atomically(|tx| Ok((rx2.pop(tx)?, rx2.try_pop(tx)?))));
+        assert_eq!(Some(1), atomically(|tx| rx3.try_peek(tx)));
+        assert!(!atomically(|tx| rx3.is_empty(tx)));
+    }
+
+    /// Dropping a receiver that lags far behind must not overflow the stack.
+    #[test]
+    fn broadcast_long() {
+        let chan = Broadcast::new();
+        let rx = chan.subscribe();
+        for i in 0..100000 {
+            atomically(|tx| chan.push(tx, i));
+        }
+        assert_eq!(0, atomically(|tx| rx.pop(tx)));
+        drop(rx);
+    }
+
+    /// Test if multiple receivers get all values from another thread.
+    #[test]
+    fn broadcast_threaded() {
+        use std::thread;
+        let chan = Broadcast::new();
+        let receivers: Vec<_> = (0..3).map(|_| chan.subscribe()).collect();
+
+        let chan2 = chan.clone();
+        thread::spawn(move || for i in 0..10 {
+            atomically(|tx| chan2.push(tx, i));
+        });
+
+        let handles: Vec<_> = receivers
+            .into_iter()
+            .map(|rx| {
+                thread::spawn(move || {
+                    (0..10).map(|_| atomically(|tx| rx.pop(tx))).collect::<Vec<_>>()
+                })
+            })
+            .collect();
+        for h in handles {
+            assert_eq!((0..10).collect::<Vec<_>>(), h.join().unwrap());
+        }
+    }
+}
@@ -0,0 +1,243 @@
+use stm::*;
+use std::any::Any;
+use super::arclist::*;
+
+// Deque uses the same two lists as `Queue`. `front` starts with the first
+// element and `back` starts with the last one.
+//
+// When one end is popped but its list is empty, the other list is split
+// in half and the older half is reversed and moved over. Moving only half
+// of the elements keeps alternating pops at both ends amortized constant
+// time, because every rebalance leaves enough elements on both sides.
+
+/// `Deque` is a threadsafe double-ended queue, that uses software transactional memory.
+///
+/// Elements can be pushed and popped at both ends.
+///
+///
+/// # Example
+///
+/// ```
+/// extern crate stm;
+/// extern crate stm_datastructures;
+///
+/// use stm::*;
+/// use stm_datastructures::Deque;
+///
+/// fn main() {
+///     let deque = Deque::new();
+///     let x = atomically(|tx| {
+///         deque.push_back(tx, 1)?;
+///         deque.push_back(tx, 2)?;
+///         deque.pop_back(tx)
+///     });
+///     assert_eq!(x, 2);
+/// }
+/// ```
+#[derive(Clone)]
+pub struct Deque<T> {
+    front: TVar<ArcList<T>>,
+    back: TVar<ArcList<T>>,
+}
+
+impl<T: Any + Sync + Clone + Send> Default for Deque<T> {
+    fn default() -> Self {
+        Deque::new()
+    }
+}
+
+impl<T: Any + Sync + Clone + Send> Deque<T> {
+    /// Create a new deque.
+    pub fn new() -> Deque<T> {
+        Deque {
+            front: TVar::new(ArcList::new()),
+            back: TVar::new(ArcList::new()),
+        }
+    }
+
+    /// Add an element to the front of the deque.
+    pub fn push_front(&self, tx: &mut Transaction, value: T) -> StmResult<()> {
+        self.front.modify(tx, |l| l.prepend(value))
+    }
+
+    /// Add an element to the back of the deque.
+    pub fn push_back(&self, tx: &mut Transaction, value: T) -> StmResult<()> {
+        self.back.modify(tx, |l| l.prepend(value))
+    }
+
+    /// Remove the first element.
+    pub fn try_pop_front(&self, tx: &mut Transaction) -> StmResult<Option<T>> {
+        try_pop_end(tx, &self.front, &self.back)
+    }
+
+    /// Remove the last element.
+    pub fn try_pop_back(&self, tx: &mut Transaction) -> StmResult<Option<T>> {
+        try_pop_end(tx, &self.back, &self.front)
+    }
+
+    /// Remove the first element or retry if the deque is empty.
+    pub fn pop_front(&self, tx: &mut Transaction) -> StmResult<T> {
+        unwrap_or_retry(self.try_pop_front(tx)?)
+    }
+
+    /// Remove the last element or retry if the deque is empty.
+    pub fn pop_back(&self, tx: &mut Transaction) -> StmResult<T> {
+        unwrap_or_retry(self.try_pop_back(tx)?)
+    }
+
+    /// Return the first element without removing it.
+    pub fn try_peek_front(&self, tx: &mut Transaction) -> StmResult<Option<T>> {
+        let v = self.try_pop_front(tx)?;
+        if let Some(ref e) = v {
+            self.push_front(tx, e.clone())?;
+        }
+        Ok(v)
+    }
+
+    /// Return the last element without removing it.
+    pub fn try_peek_back(&self, tx: &mut Transaction) -> StmResult<Option<T>> {
+        let v = self.try_pop_back(tx)?;
+        if let Some(ref e) = v {
+            self.push_back(tx, e.clone())?;
+        }
+        Ok(v)
+    }
+
+    /// Return the first element without removing it or retry if the deque is empty.
+    pub fn peek_front(&self, tx: &mut Transaction) -> StmResult<T> {
+        unwrap_or_retry(self.try_peek_front(tx)?)
+    }
+
+    /// Return the last element without removing it or retry if the deque is empty.
+    pub fn peek_back(&self, tx: &mut Transaction) -> StmResult<T> {
+        unwrap_or_retry(self.try_peek_back(tx)?)
+    }
+
+    /// Check if the deque is empty.
+    pub fn is_empty(&self, tx: &mut Transaction) -> StmResult<bool> {
+        Ok(self.front.read(tx)?.is_empty() && self.back.read(tx)?.is_empty())
+    }
+}
+
+/// Pop from the list `this` and refill it from `other` if it is empty.
+fn try_pop_end<T>(
+    tx: &mut Transaction,
+    this: &TVar<ArcList<T>>,
+    other: &TVar<ArcList<T>>,
+) -> StmResult<Option<T>>
+where
+    T: Any + Sync + Clone + Send,
+{
+    if let Some((x, xs)) = this.read(tx)?.into_splitted() {
+        this.write(tx, xs)?;
+        return Ok(Some(x));
+    }
+
+    let other_list = other.read(tx)?;
+    if other_list.is_empty() {
+        return Ok(None);
+    }
+    let (keep, moved) = split_half(&other_list);
+    other.write(tx, keep)?;
+    Ok(match moved.into_splitted() {
+        Some((x, xs)) => {
+            this.write(tx, xs)?;
+            Some(x)
+        }
+        None => None,
+    })
+}
+
+/// Split a list into its first half and the reversed second half.
+///
+/// For an odd length the second half gets the additional element, so
+/// that it is never empty for a non-empty list.
+fn split_half<T: Clone>(list: &ArcList<T>) -> (ArcList<T>, ArcList<T>) {
+    let k = list.len() / 2;
+    let mut iter = list.iter();
+    let keep = iter.by_ref().take(k).cloned().collect();
+    let mut moved = ArcList::new();
+    for x in iter {
+        moved.push(x.clone());
+    }
+    (keep, moved)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use stm::*;
+    use super::*;
+
+    /// Check if both ends work as FIFO and LIFO.
+    #[test]
+    fn deque_order() {
+        let deque = Deque::new();
+        let x = atomically(|tx| {
+            deque.push_back(tx, 2)?;
+            deque.push_back(tx, 3)?;
+            deque.push_front(tx, 1)?;
+            let x1 = deque.pop_front(tx)?;
+            let x2 = deque.pop_front(tx)?;
+            let x3 = deque.pop_front(tx)?;
+            Ok((x1, x2, x3))
+        });
+        assert_eq!((1, 2, 3), x);
+
+        let x = atomically(|tx| {
+            deque.push_back(tx, 1)?;
+            deque.push_back(tx, 2)?;
+            deque.push_back(tx, 3)?;
+            let x1 = deque.pop_back(tx)?;
+            let x2 = deque.pop_back(tx)?;
+            let x3 = deque.pop_back(tx)?;
+            Ok((x1, x2, x3))
+        });
+        assert_eq!((3, 2, 1), x);
+        assert!(atomically(|tx| deque.is_empty(tx)));
+    }
+
+    /// Alternate between both ends, so that the lists have to be
+    /// rebalanced repeatedly.
+    #[test]
+    fn deque_alternating() {
+        let deque = Deque::new();
+        atomically(|tx| {
+            for i in 0..100 {
+                deque.push_back(tx, i)?;
+            }
+            Ok(())
+        });
+
+        let mut lo = 0;
+        let mut hi = 99;
+        while lo <= hi {
+            assert_eq!(Some(lo), atomically(|tx| deque.try_peek_front(tx)));
+            assert_eq!(lo, atomically(|tx| deque.pop_front(tx)));
+            lo += 1;
+            if lo <= hi {
+                assert_eq!(Some(hi), atomically(|tx| deque.try_peek_back(tx)));
+                assert_eq!(hi, atomically(|tx| deque.pop_back(tx)));
+                hi -= 1;
+            }
+        }
+        assert_eq!(None, atomically(|tx| deque.try_pop_back(tx)));
+        assert_eq!(None, atomically(|tx| deque.try_pop_front(tx)));
+    }
+
+    /// Test if a blocked `pop_back` is woken up by another thread.
+    #[test]
+    fn deque_threaded() {
+        use std::thread;
+        use std::time::Duration;
+        let deque = Deque::new();
+        let deque2 = deque.clone();
+
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            atomically(|tx| deque2.push_front(tx, 42));
+        });
+
+        assert_eq!(42, atomically(|tx| deque.pop_back(tx)));
+    }
+}
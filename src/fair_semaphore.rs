@@ -0,0 +1,167 @@
+use stm::*;
+use super::Queue;
+
+// A waiter, that can not get a token immediately, enqueues its own
+// `TVar<bool>` (a ticket) and blocks on it in a second transaction.
+// `release` hands the token directly to the oldest ticket instead of
+// increasing the counter. Thereby only a single waiter is woken up
+// and tokens are granted in arrival order.
+//
+// The counter is only positive, if no ticket is waiting. Therefore
+// `try_acquire` can never overtake a waiting thread.
+
+/// `FairSemaphore` is a semaphore, that grants tokens in FIFO order.
+///
+/// Unlike `Semaphore::wait`, waiting for a token can not be done inside of
+/// a single transaction, because the place in the queue has to be committed
+/// before blocking. Use `acquire` from outside of a transaction instead.
+///
+///
+/// # Example
+///
+/// ```
+/// extern crate stm;
+/// extern crate stm_datastructures;
+///
+/// use stm::*;
+/// use stm_datastructures::FairSemaphore;
+///
+/// fn main() {
+///     let sem = FairSemaphore::new(1);
+///     sem.acquire();
+///     atomically(|tx| sem.release(tx));
+/// }
+/// ```
+#[derive(Clone)]
+pub struct FairSemaphore {
+    /// Number of free tokens.
+    num: TVar<u32>,
+
+    /// Tickets of the waiting threads in arrival order.
+    waiters: Queue<TVar<bool>>,
+}
+
+impl FairSemaphore {
+    /// Create a new semaphore with `n` initial tokens.
+    pub fn new(n: u32) -> FairSemaphore {
+        FairSemaphore {
+            num: TVar::new(n),
+            waiters: Queue::new(),
+        }
+    }
+
+    /// Take a token or block until it is this thread's turn.
+    ///
+    /// This runs its own transactions and therefore must not be called
+    /// inside of a transaction.
+    pub fn acquire(&self) {
+        let ticket = atomically(|tx| {
+            if self.try_acquire(tx)? {
+                return Ok(None);
+            }
+            let ticket = TVar::new(false);
+            self.waiters
+                .push(tx, ticket.clone())?
+                .expect("ticket queue is never closed");
+            Ok(Some(ticket))
+        });
+        if let Some(ticket) = ticket {
+            atomically(|tx| guard(ticket.read(tx)?));
+        }
+    }
+
+    /// Take a token if one is available and nobody is waiting.
+    pub fn try_acquire(&self, tx: &mut Transaction) -> StmResult<bool> {
+        let n = self.num.read(tx)?;
+        if n == 0 {
+            return Ok(false);
+        }
+        self.num.write(tx, n - 1)?;
+        Ok(true)
+    }
+
+    /// Free a token and hand it to the longest waiting thread.
+    pub fn release(&self, tx: &mut Transaction) -> StmResult<()> {
+        match self.waiters.try_pop(tx)? {
+            Some(ticket) => ticket.write(tx, true),
+            None => self.num.modify(tx, |n| n + 1),
+        }
+    }
+
+    /// Return the number of free tokens.
+    pub fn available(&self, tx: &mut Transaction) -> StmResult<u32> {
+        self.num.read(tx)
+    }
+
+    /// Return the number of threads waiting for a token.
+    pub fn waiting(&self, tx: &mut Transaction) -> StmResult<usize> {
+        self.waiters.len(tx)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Test if release and acquire combo works.
+    #[test]
+    fn fsem_release_acquire() {
+        let sem = FairSemaphore::new(0);
+        atomically(|tx| sem.release(tx));
+        sem.acquire();
+        assert!(!atomically(|tx| sem.try_acquire(tx)));
+    }
+
+    /// Test if the semaphore works with more than one thread.
+    #[test]
+    fn fsem_threaded() {
+        use std::thread;
+
+        let sem = FairSemaphore::new(0);
+
+        for _ in 0..10 {
+            let sem2 = sem.clone();
+            thread::spawn(move || { atomically(|tx| sem2.release(tx)); });
+        }
+
+        for _ in 0..10 {
+            sem.acquire();
+        }
+    }
+
+    /// Waiting threads get their tokens in arrival order.
+    ///
+    /// With `Semaphore` the order would be arbitrary and a thread
+    /// could starve under contention.
+    #[test]
+    fn fsem_fifo() {
+        use std::thread;
+
+        let sem = FairSemaphore::new(0);
+        let order = Queue::new();
+
+        let mut handles = Vec::new();
+        for i in 0..10 {
+            let sem2 = sem.clone();
+            let order2 = order.clone();
+            handles.push(thread::spawn(move || {
+                sem2.acquire();
+                atomically(|tx| order2.push(tx, i)).unwrap();
+            }));
+            // Wait until the thread is queued, before starting the next one.
+            atomically(|tx| guard(sem.waiting(tx)? == i + 1));
+        }
+
+        // Release the tokens one by one, so that the threads
+        // record themselves in the order they are woken up.
+        for i in 0..10 {
+            atomically(|tx| sem.release(tx));
+            atomically(|tx| guard(order.len(tx)? == i + 1));
+        }
+        for h in handles {
+            h.join().unwrap();
+        }
+
+        assert_eq!((0..10).collect::<Vec<_>>(), atomically(|tx| order.drain(tx)));
+    }
+}
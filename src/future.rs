@@ -0,0 +1,365 @@
+use stm::*;
+use std::any::Any;
+use std::cell::Cell;
+use std::future::Future;
+use std::panic::{self, AssertUnwindSafe};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::task::{Context, Poll, Waker};
+use std::thread;
+
+// `poll` runs the transaction once without blocking by wrapping it into
+// `optionally`. If it retries, the future is registered with a single
+// waiter thread shared by all futures. That thread waits until one of the
+// registered transactions would succeed and then wakes its task, which
+// polls again.
+//
+// The waiter runs every registered transaction in the first branch of `or`
+// and always calls `retry` afterwards. This discards all writes, but `or`
+// keeps the reads, so the waiter blocks on exactly the variables, that the
+// transactions depend on. A `Cell` per transaction tells, which of them
+// would have succeeded. The waiter also reads `changed`, which is
+// incremented for every new registration, so that it restarts with the
+// new set of transactions.
+//
+// Every wake-up of the waiter runs all registered transactions again, so
+// it costs `O(n)` in the number of pending futures, and `n` registrations
+// cost `O(n²)` in total. This is fine for a moderate number of futures.
+//
+// A panic in one of the transactions must not kill the waiter, because all
+// other futures would stay pending forever. It is caught, the future is
+// woken up and its next `poll` resumes the panic.
+//
+// Dropping a future only sets its `cancelled` flag, so that it can be
+// dropped inside of a transaction. The waiter removes cancelled futures,
+// whenever it wakes up.
+
+/// Run a transaction asynchronously.
+///
+/// The returned future completes with the result of `f`. Instead of
+/// blocking the current thread, when `f` retries, the task is suspended
+/// until one of the variables read by `f` changes.
+///
+/// While the future is pending, `f` also runs on a background thread
+/// shared by all futures, which reruns every pending transaction each
+/// time one of them may have become ready. If `f` panics there, the panic
+/// is resumed by the next `poll`.
+///
+///
+/// # Example
+///
+/// ```
+/// extern crate stm;
+/// extern crate stm_datastructures;
+///
+/// use stm::*;
+/// use stm_datastructures::future::atomically_async;
+///
+/// fn main() {
+///     let var = TVar::new(42);
+///     // Await this inside of an async task.
+///     let _future = atomically_async(move |tx| var.read(tx));
+/// }
+/// ```
+pub fn atomically_async<T, F>(f: F) -> Atomically<T, F>
+where
+    F: Fn(&mut Transaction) -> StmResult<T> + Send + Sync + 'static,
+{
+    Atomically {
+        f: Arc::new(f),
+        shared: Arc::new(Shared {
+            waker: Mutex::new(None),
+            waiting: AtomicBool::new(false),
+            cancelled: AtomicBool::new(false),
+            panic: Mutex::new(None),
+        }),
+    }
+}
+
+/// Future returned by `atomically_async`.
+pub struct Atomically<T, F>
+where
+    F: Fn(&mut Transaction) -> StmResult<T>,
+{
+    f: Arc<F>,
+    shared: Arc<Shared>,
+}
+
+/// State shared between the future and the waiter thread.
+struct Shared {
+    /// Waker of the last poll.
+    waker: Mutex<Option<Waker>>,
+
+    /// Set while the future is registered with the waiter.
+    waiting: AtomicBool,
+
+    /// Set, when the future is dropped.
+    cancelled: AtomicBool,
+
+    /// Panic of the transaction on the waiter thread.
+    panic: Mutex<Option<Panic>>,
+}
+
+/// Payload of a caught panic.
+type Panic = Box<dyn Any + Send>;
+
+impl<T, F> Future for Atomically<T, F>
+where
+    F: Fn(&mut Transaction) -> StmResult<T> + Send + Sync + 'static,
+{
+    type Output = T;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<T> {
+        if let Some(payload) = self.shared.panic.lock().unwrap().take() {
+            panic::resume_unwind(payload);
+        }
+        let f = &*self.f;
+        if let Some(x) = atomically(|tx| optionally(tx, f)) {
+            return Poll::Ready(x);
+        }
+
+        *self.shared.waker.lock().unwrap() = Some(cx.waker().clone());
+        if !self.shared.waiting.swap(true, Ordering::SeqCst) {
+            let f = self.f.clone();
+            waiter().register(Wait {
+                check: Arc::new(move |tx| f(tx).map(|_| ())),
+                shared: self.shared.clone(),
+            });
+        }
+        Poll::Pending
+    }
+}
+
+impl<T, F> Drop for Atomically<T, F>
+where
+    F: Fn(&mut Transaction) -> StmResult<T>,
+{
+    fn drop(&mut self) {
+        self.shared.cancelled.store(true, Ordering::SeqCst);
+    }
+}
+
+/// The transaction of a future without its result.
+type Check = Arc<dyn Fn(&mut Transaction) -> StmResult<()> + Send + Sync>;
+
+/// A future registered with the waiter thread.
+#[derive(Clone)]
+struct Wait {
+    check: Check,
+    shared: Arc<Shared>,
+}
+
+struct Waiter {
+    waits: Mutex<Vec<Wait>>,
+    changed: TVar<u64>,
+}
+
+/// Return the global waiter and start its thread if necessary.
+fn waiter() -> &'static Waiter {
+    static WAITER: OnceLock<Waiter> = OnceLock::new();
+    WAITER.get_or_init(|| {
+        thread::spawn(|| waiter().run());
+        Waiter {
+            waits: Mutex::new(Vec::new()),
+            changed: TVar::new(0),
+        }
+    })
+}
+
+impl Waiter {
+    fn register(&self, wait: Wait) {
+        self.waits.lock().unwrap().push(wait);
+        atomically(|tx| self.changed.modify(tx, |x| x.wrapping_add(1)));
+    }
+
+    fn run(&self) {
+        loop {
+            // Read `changed` first, so that no registration is missed.
+            let seen = atomically(|tx| self.changed.read(tx));
+            let waits = {
+                let mut waits = self.waits.lock().unwrap();
+                waits.retain(|w| !w.shared.cancelled.load(Ordering::SeqCst));
+                waits.clone()
+            };
+            let ready: Vec<_> = waits.iter().map(|_| Cell::new(false)).collect();
+            let panics: Vec<Cell<Option<Panic>>> = waits.iter().map(|_| Cell::new(None)).collect();
+            atomically(|tx| {
+                let mut any = self.changed.read(tx)? != seen;
+                for ((w, r), p) in waits.iter().zip(&ready).zip(&panics) {
+                    r.set(false);
+                    p.set(None);
+                    tx.or(
+                        |tx| {
+                            match panic::catch_unwind(AssertUnwindSafe(|| (w.check)(tx))) {
+                                Ok(result) => {
+                                    result?;
+                                }
+                                Err(payload) => p.set(Some(payload)),
+                            }
+                            r.set(true);
+                            retry()
+                        },
+                        |_| Ok(()),
+                    )?;
+                    any |= r.get();
+                }
+                guard(any)
+            });
+
+            let mut woken = Vec::new();
+            for ((w, r), p) in waits.iter().zip(&ready).zip(&panics) {
+                if r.get() {
+                    if let Some(payload) = p.take() {
+                        *w.shared.panic.lock().unwrap() = Some(payload);
+                    }
+                    woken.push(w.shared.clone());
+                }
+            }
+            self.waits
+                .lock()
+                .unwrap()
+                .retain(|w| !woken.iter().any(|s| Arc::ptr_eq(s, &w.shared)));
+            for shared in woken {
+                shared.waiting.store(false, Ordering::SeqCst);
+                if let Some(waker) = shared.waker.lock().unwrap().take() {
+                    waker.wake();
+                }
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use stm::*;
+    use super::*;
+    use std::sync::mpsc;
+    use std::thread::Thread;
+    use std::task::Wake;
+    use std::time::Duration;
+    use super::super::{Queue, BoundedQueue, Semaphore};
+
+    struct ThreadWaker(Thread);
+
+    impl Wake for ThreadWaker {
+        fn wake(self: Arc<Self>) {
+            self.0.unpark();
+        }
+    }
+
+    /// Minimal executor, that parks the current thread while pending.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let mut future = Box::pin(future);
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        loop {
+            if let Poll::Ready(x) = future.as_mut().poll(&mut cx) {
+                return x;
+            }
+            thread::park();
+        }
+    }
+
+    /// A ready transaction completes on the first poll.
+    #[test]
+    fn async_ready() {
+        let var = TVar::new(42);
+        let var2 = var.clone();
+        assert_eq!(42, block_on(atomically_async(move |tx| var2.read(tx))));
+    }
+
+    /// A retrying transaction is woken up by a commit from another thread.
+    #[test]
+    fn async_wakeup() {
+        let queue = Queue::new();
+        let bqueue = BoundedQueue::new(1);
+        let sem = Semaphore::new(0);
+
+        let queue2 = queue.clone();
+        let bqueue2 = bqueue.clone();
+        let sem2 = sem.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            queue2.push_now(1).unwrap();
+            assert_eq!(Ok(0), bqueue2.pop_blocking());
+            sem2.signal_now();
+        });
+
+        bqueue.push_now(0).unwrap();
+        assert_eq!(Ok(1), block_on(queue.pop_async()));
+        block_on(bqueue.push_async(2)).unwrap();
+        block_on(sem.acquire_async());
+        assert_eq!(Ok(2), bqueue.pop_blocking());
+    }
+
+    /// A panic on the waiter thread is resumed by `poll` and does not
+    /// affect other futures.
+    #[test]
+    fn async_panic() {
+        use std::panic;
+        let var = TVar::new(0);
+        let var2 = var.clone();
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let mut future = Box::pin(atomically_async(move |tx| {
+            let x = var2.read(tx)?;
+            assert!(x != 1, "boom");
+            guard(x == 2)
+        }));
+        assert!(future.as_mut().poll(&mut cx).is_pending());
+        atomically(|tx| var.write(tx, 1));
+        // Wait until the waiter has run into the panic.
+        while future.shared.waiting.load(Ordering::SeqCst) {
+            thread::sleep(Duration::from_millis(1));
+        }
+        assert!(future.shared.panic.lock().unwrap().is_some());
+        let result = panic::catch_unwind(AssertUnwindSafe(|| future.as_mut().poll(&mut cx)));
+        assert!(result.is_err());
+
+        let queue = Queue::new();
+        let queue2 = queue.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            queue2.push_now(1).unwrap();
+        });
+        assert_eq!(Ok(1), block_on(queue.pop_async()));
+    }
+
+    /// A dropped future is removed from the waiter without a transaction.
+    #[test]
+    fn async_cancel() {
+        let var = TVar::new(false);
+        let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+        let mut cx = Context::from_waker(&waker);
+        let shared = {
+            let var = var.clone();
+            let mut future = Box::pin(atomically_async(move |tx| guard(var.read(tx)?)));
+            assert!(future.as_mut().poll(&mut cx).is_pending());
+            future.shared.clone()
+        };
+
+        // Wake up the waiter with another future, so that it notices
+        // the cancelled one.
+        let other = TVar::new(false);
+        let other2 = other.clone();
+        let mut future = Box::pin(atomically_async(move |tx| guard(other2.read(tx)?)));
+        assert!(future.as_mut().poll(&mut cx).is_pending());
+        atomically(|tx| other.write(tx, true));
+        while future.as_mut().poll(&mut cx).is_pending() {
+            thread::park_timeout(Duration::from_millis(10));
+        }
+
+        let (send, recv) = mpsc::channel();
+        thread::spawn(move || {
+            // Only this test holds a reference once the waiter let go.
+            while Arc::strong_count(&shared) > 1 {
+                thread::sleep(Duration::from_millis(1));
+            }
+            send.send(()).unwrap();
+        });
+        recv.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert!(!atomically(|tx| var.read(tx)));
+    }
+}
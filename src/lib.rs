@@ -1,11 +1,48 @@
 extern crate stm;
 
 pub mod arclist;
+pub mod arcvec;
+pub mod arcmap;
 pub mod queue;
 pub mod bounded_queue;
+pub mod owned_queue;
+pub mod owned_bounded_queue;
+pub mod deque;
+pub mod priority_queue;
 pub mod semaphore;
+pub mod fair_semaphore;
+pub mod tmvar;
+pub mod broadcast;
+pub mod tmap;
+pub mod tbtree_map;
+pub mod thash_set;
+pub mod tbtree_set;
+pub mod tarray;
+pub mod tvec;
+pub mod timeout;
+pub mod select;
+#[cfg(feature = "async")]
+pub mod future;
+mod raw;
 
-pub use queue::Queue;
+pub use queue::{Queue, Closed, TSender, TReceiver};
 pub use bounded_queue::BoundedQueue;
-pub use semaphore::Semaphore;
+pub use owned_queue::{OwnedQueue, Owned};
+pub use owned_bounded_queue::OwnedBoundedQueue;
+pub use deque::Deque;
+pub use priority_queue::PriorityQueue;
+pub use semaphore::{Semaphore, SemaphoreGuard};
+pub use fair_semaphore::FairSemaphore;
+pub use tmvar::TMVar;
+pub use broadcast::{Broadcast, BroadcastReceiver};
+pub use tmap::TMap;
+pub use tbtree_map::TBTreeMap;
+pub use thash_set::THashSet;
+pub use tbtree_set::TBTreeSet;
+pub use tarray::TArray;
+pub use tvec::TVec;
+pub use timeout::Timeout;
+pub use select::{select, Receive};
 pub use arclist::{ArcList, IterRef, IterClone};
+pub use arcvec::ArcVec;
+pub use arcmap::ArcMap;
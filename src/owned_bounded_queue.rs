@@ -0,0 +1,193 @@
+use stm::*;
+use super::BoundedQueue;
+use super::owned_queue::Owned;
+use super::queue::Closed;
+
+// `OwnedBoundedQueue` stores `Owned` handles in a `BoundedQueue` just like
+// `OwnedQueue` does with a `Queue`. See `owned_queue` for the reasoning.
+//
+// Every handle takes one slot of the capacity, including handles, whose
+// value has already been taken through another clone.
+
+/// `OwnedBoundedQueue` is a threadsafe FIFO queue with a maximal size for
+/// values, that can not be cloned.
+///
+/// It is to `OwnedQueue` what `BoundedQueue` is to `Queue`.
+///
+///
+/// # Example
+///
+/// ```
+/// extern crate stm;
+/// extern crate stm_datastructures;
+///
+/// use stm_datastructures::OwnedBoundedQueue;
+///
+/// fn main() {
+///     let queue: OwnedBoundedQueue<Box<dyn FnOnce() -> i32 + Send>> = OwnedBoundedQueue::new(1);
+///     queue.push_now(Box::new(|| 42)).unwrap();
+///     let job = queue.pop_blocking().unwrap();
+///     assert_eq!(job(), 42);
+/// }
+/// ```
+pub struct OwnedBoundedQueue<T> {
+    queue: BoundedQueue<Owned<T>>,
+}
+
+impl<T> Clone for OwnedBoundedQueue<T> {
+    fn clone(&self) -> Self {
+        OwnedBoundedQueue { queue: self.queue.clone() }
+    }
+}
+
+impl<T: Send + 'static> OwnedBoundedQueue<T> {
+    /// Create a new queue, that can hold maximally `capacity` values.
+    pub fn new(capacity: usize) -> OwnedBoundedQueue<T> {
+        OwnedBoundedQueue { queue: BoundedQueue::new(capacity) }
+    }
+
+    /// Create a new queue in fair mode.
+    ///
+    /// See `BoundedQueue::new_fair`.
+    pub fn new_fair(capacity: usize) -> OwnedBoundedQueue<T> {
+        OwnedBoundedQueue { queue: BoundedQueue::new_fair(capacity) }
+    }
+
+    /// Add a value to the end of the queue.
+    ///
+    /// Retry while the queue is full and fail if it is closed.
+    pub fn push(&self, tx: &mut Transaction, value: Owned<T>) -> StmResult<Result<(), Closed>> {
+        self.queue.push(tx, value)
+    }
+
+    /// Remove the first value without blocking.
+    pub fn try_pop(&self, tx: &mut Transaction) -> StmResult<Option<Owned<T>>> {
+        self.queue.try_pop(tx)
+    }
+
+    /// Remove the first value.
+    ///
+    /// Retry if the queue is empty. Once the queue is closed and all
+    /// values have been removed, fail instead of retrying.
+    pub fn pop(&self, tx: &mut Transaction) -> StmResult<Result<Owned<T>, Closed>> {
+        self.queue.pop(tx)
+    }
+
+    /// Return the number of handles in the queue.
+    ///
+    /// Like `OwnedQueue::len` this includes handles, whose value has
+    /// already been taken.
+    pub fn len(&self, tx: &mut Transaction) -> StmResult<usize> {
+        self.queue.len(tx)
+    }
+
+    /// Check if the queue contains no handles.
+    pub fn is_empty(&self, tx: &mut Transaction) -> StmResult<bool> {
+        self.queue.is_empty(tx)
+    }
+
+    /// Return the maximal number of handles.
+    pub fn capacity(&self) -> usize {
+        self.queue.capacity()
+    }
+
+    /// Return the number of handles, that still fit into the queue.
+    pub fn remaining(&self, tx: &mut Transaction) -> StmResult<usize> {
+        self.queue.remaining(tx)
+    }
+
+    /// Close the queue.
+    ///
+    /// Further pushes fail. Remaining values can still be popped.
+    pub fn close(&self, tx: &mut Transaction) -> StmResult<()> {
+        self.queue.close(tx)
+    }
+
+    /// Check if the queue has been closed.
+    pub fn is_closed(&self, tx: &mut Transaction) -> StmResult<bool> {
+        self.queue.is_closed(tx)
+    }
+
+    /// Add a value to the end of the queue in its own transaction.
+    ///
+    /// Block while the queue is full. Like all `_now` and `_blocking`
+    /// methods, this must not be called inside of a transaction.
+    pub fn push_now(&self, value: T) -> Result<(), Closed> {
+        self.queue.push_now(Owned::new(value))
+    }
+
+    /// Remove the first value in its own transaction.
+    ///
+    /// Block while the queue is empty and fail once it is closed.
+    pub fn pop_blocking(&self) -> Result<T, Closed> {
+        loop {
+            // Skip values, that have been taken through another handle.
+            if let Some(x) = self.queue.pop_blocking()?.take() {
+                return Ok(x);
+            }
+        }
+    }
+
+    /// Remove the first value in its own transaction without blocking.
+    pub fn try_pop_now(&self) -> Option<T> {
+        while let Some(value) = self.queue.try_pop_now() {
+            if let Some(x) = value.take() {
+                return Some(x);
+            }
+        }
+        None
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use stm::*;
+    use super::*;
+
+    /// Neither `Clone` nor `Sync`.
+    struct Job(Box<dyn FnOnce() -> usize + Send>);
+
+    /// Values pass the queue in FIFO order and take up space.
+    #[test]
+    fn owned_bounded_push_pop() {
+        let queue = OwnedBoundedQueue::new(2);
+        queue.push_now(Job(Box::new(|| 0))).unwrap();
+        queue.push_now(Job(Box::new(|| 1))).unwrap();
+        assert_eq!(0, atomically(|tx| queue.remaining(tx)));
+        assert_eq!(0, (queue.pop_blocking().unwrap().0)());
+        let job = Owned::new(Job(Box::new(|| 2)));
+        atomically(|tx| queue.push(tx, job.clone())).unwrap();
+
+        assert_eq!(1, (queue.pop_blocking().unwrap().0)());
+        assert_eq!(2, (queue.try_pop_now().unwrap().0)());
+        assert!(queue.try_pop_now().is_none());
+        assert_eq!(2, atomically(|tx| queue.remaining(tx)));
+
+        atomically(|tx| queue.close(tx));
+        assert_eq!(Err(Closed), queue.push_now(Job(Box::new(|| 3))));
+        assert!(queue.pop_blocking().is_err());
+    }
+
+    /// A producer blocks until a worker makes space.
+    #[test]
+    fn owned_bounded_threaded() {
+        use std::thread;
+        let queue: OwnedBoundedQueue<Job> = OwnedBoundedQueue::new_fair(1);
+
+        let queue2 = queue.clone();
+        let worker = thread::spawn(move || {
+            let mut sum = 0;
+            while let Ok(job) = queue2.pop_blocking() {
+                sum += (job.0)();
+            }
+            sum
+        });
+
+        for i in 0..100 {
+            queue.push_now(Job(Box::new(move || i))).unwrap();
+        }
+        atomically(|tx| queue.close(tx));
+        assert_eq!((0..100).sum::<usize>(), worker.join().unwrap());
+    }
+}
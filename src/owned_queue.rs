@@ -0,0 +1,242 @@
+use stm::*;
+use std::sync::{Arc, Mutex};
+use super::Queue;
+use super::queue::Closed;
+
+// Transactions may run several times, so values can not be moved in or out
+// of a transaction. `OwnedQueue` therefore stores `Owned` cells, which are
+// cheap to clone, and the value is moved out of the cell after the
+// transaction, that removed it from the queue, has committed. Only one
+// transaction can commit the removal of a cell, so only its caller gets
+// the value.
+//
+// The cell uses a `Mutex`, so that it is `Sync` for any `T: Send`.
+//
+// The same handle can be pushed several times inside of a transaction.
+// Only the first removal gets the value, the others are skipped by
+// `pop_blocking` and `try_pop_now`. `len` still counts every handle.
+
+/// A value, that can be moved through an `OwnedQueue`.
+///
+/// Cloning an `Owned` creates another handle to the same value. The value
+/// can only be taken out once.
+pub struct Owned<T> {
+    cell: Arc<Mutex<Option<T>>>,
+}
+
+impl<T> Clone for Owned<T> {
+    fn clone(&self) -> Self {
+        Owned { cell: self.cell.clone() }
+    }
+}
+
+impl<T> Owned<T> {
+    /// Wrap `value` into a new cell.
+    pub fn new(value: T) -> Owned<T> {
+        Owned { cell: Arc::new(Mutex::new(Some(value))) }
+    }
+
+    /// Move the value out of the cell.
+    ///
+    /// Return `None` if it has already been taken through another handle.
+    /// Don't call this inside of a transaction, because the transaction
+    /// may be undone, but the value stays taken.
+    pub fn take(&self) -> Option<T> {
+        self.cell.lock().unwrap().take()
+    }
+}
+
+/// `OwnedQueue` is a threadsafe FIFO queue for values, that can not be cloned.
+///
+/// Unlike `Queue` it only requires `T: Send`, which allows to send handles
+/// like files or boxed closures. Inside of transactions the values are
+/// represented by `Owned` handles.
+///
+///
+/// # Example
+///
+/// ```
+/// extern crate stm;
+/// extern crate stm_datastructures;
+///
+/// use stm_datastructures::OwnedQueue;
+///
+/// fn main() {
+///     let queue: OwnedQueue<Box<dyn FnOnce() -> i32 + Send>> = OwnedQueue::new();
+///     queue.push_now(Box::new(|| 42)).unwrap();
+///     let job = queue.pop_blocking().unwrap();
+///     assert_eq!(job(), 42);
+/// }
+/// ```
+pub struct OwnedQueue<T> {
+    queue: Queue<Owned<T>>,
+}
+
+impl<T> Clone for OwnedQueue<T> {
+    fn clone(&self) -> Self {
+        OwnedQueue { queue: self.queue.clone() }
+    }
+}
+
+impl<T: Send + 'static> Default for OwnedQueue<T> {
+    fn default() -> Self {
+        OwnedQueue::new()
+    }
+}
+
+impl<T: Send + 'static> OwnedQueue<T> {
+    /// Create a new queue.
+    pub fn new() -> OwnedQueue<T> {
+        OwnedQueue { queue: Queue::new() }
+    }
+
+    /// Add a value to the end of the queue.
+    ///
+    /// Fail if the queue is closed. Pushing clones of the same handle
+    /// adds it several times, but the value can only be taken once.
+    pub fn push(&self, tx: &mut Transaction, value: Owned<T>) -> StmResult<Result<(), Closed>> {
+        self.queue.push(tx, value)
+    }
+
+    /// Remove the first value without blocking.
+    pub fn try_pop(&self, tx: &mut Transaction) -> StmResult<Option<Owned<T>>> {
+        self.queue.try_pop(tx)
+    }
+
+    /// Remove the first value.
+    ///
+    /// Retry if the queue is empty. Once the queue is closed and all
+    /// values have been removed, fail instead of retrying.
+    pub fn pop(&self, tx: &mut Transaction) -> StmResult<Result<Owned<T>, Closed>> {
+        self.queue.pop(tx)
+    }
+
+    /// Return the number of handles in the queue.
+    ///
+    /// This includes handles, whose value has already been taken through
+    /// another handle, so it may be larger than the number of values,
+    /// that `pop_blocking` will return.
+    pub fn len(&self, tx: &mut Transaction) -> StmResult<usize> {
+        self.queue.len(tx)
+    }
+
+    /// Check if the queue contains no handles.
+    pub fn is_empty(&self, tx: &mut Transaction) -> StmResult<bool> {
+        self.queue.is_empty(tx)
+    }
+
+    /// Close the queue.
+    ///
+    /// Further pushes fail. Remaining values can still be popped.
+    pub fn close(&self, tx: &mut Transaction) -> StmResult<()> {
+        self.queue.close(tx)
+    }
+
+    /// Check if the queue has been closed.
+    pub fn is_closed(&self, tx: &mut Transaction) -> StmResult<bool> {
+        self.queue.is_closed(tx)
+    }
+
+    /// Add a value to the end of the queue in its own transaction.
+    ///
+    /// Like all `_now` and `_blocking` methods, this must not be called
+    /// inside of a transaction.
+    pub fn push_now(&self, value: T) -> Result<(), Closed> {
+        let value = Owned::new(value);
+        atomically(|tx| self.push(tx, value.clone()))
+    }
+
+    /// Remove the first value in its own transaction.
+    ///
+    /// Block while the queue is empty and fail once it is closed.
+    pub fn pop_blocking(&self) -> Result<T, Closed> {
+        loop {
+            // Skip values, that have been taken through another handle.
+            if let Some(x) = atomically(|tx| self.pop(tx))?.take() {
+                return Ok(x);
+            }
+        }
+    }
+
+    /// Remove the first value in its own transaction without blocking.
+    pub fn try_pop_now(&self) -> Option<T> {
+        while let Some(value) = atomically(|tx| self.try_pop(tx)) {
+            if let Some(x) = value.take() {
+                return Some(x);
+            }
+        }
+        None
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use stm::*;
+    use super::*;
+
+    /// Neither `Clone` nor `Sync`.
+    struct Job(Box<dyn FnOnce() -> usize + Send>);
+
+    /// Values pass the queue in FIFO order.
+    #[test]
+    fn owned_push_pop() {
+        let queue = OwnedQueue::new();
+        for i in 0..3 {
+            queue.push_now(Job(Box::new(move || i))).unwrap();
+        }
+        let first = atomically(|tx| queue.pop(tx)).unwrap();
+        assert_eq!(0, (first.take().unwrap().0)());
+        assert!(first.take().is_none());
+        assert_eq!(1, (queue.pop_blocking().unwrap().0)());
+        assert_eq!(2, (queue.try_pop_now().unwrap().0)());
+        assert!(queue.try_pop_now().is_none());
+
+        atomically(|tx| queue.close(tx));
+        assert_eq!(Err(Closed), queue.push_now(Job(Box::new(|| 3))));
+        assert!(queue.pop_blocking().is_err());
+    }
+
+    /// A handle pushed twice only delivers its value once.
+    #[test]
+    fn owned_duplicate() {
+        let queue = OwnedQueue::new();
+        let value = Owned::new(Job(Box::new(|| 1)));
+        atomically(|tx| {
+            queue.push(tx, value.clone())?.unwrap();
+            queue.push(tx, value.clone())
+        }).unwrap();
+        queue.push_now(Job(Box::new(|| 2))).unwrap();
+
+        assert_eq!(1, (queue.pop_blocking().unwrap().0)());
+        assert_eq!(2, (queue.pop_blocking().unwrap().0)());
+    }
+
+    /// Send jobs to worker threads.
+    #[test]
+    fn owned_threaded() {
+        use std::thread;
+        let queue: OwnedQueue<Job> = OwnedQueue::new();
+
+        let workers: Vec<_> = (0..4)
+            .map(|_| {
+                let queue = queue.clone();
+                thread::spawn(move || {
+                    let mut sum = 0;
+                    while let Ok(job) = queue.pop_blocking() {
+                        sum += (job.0)();
+                    }
+                    sum
+                })
+            })
+            .collect();
+
+        for i in 0..100 {
+            queue.push_now(Job(Box::new(move || i))).unwrap();
+        }
+        atomically(|tx| queue.close(tx));
+
+        let sum: usize = workers.into_iter().map(|h| h.join().unwrap()).sum();
+        assert_eq!((0..100).sum::<usize>(), sum);
+    }
+}
@@ -0,0 +1,300 @@
+use stm::*;
+use std::any::Any;
+use std::sync::Arc;
+
+// The priority queue is a persistent leftist heap stored in a single `TVar`.
+// Just like `ArcList` the nodes are shared between versions, so a
+// modification only copies the right spine of the heap, which has at
+// most logarithmic length.
+
+struct Node<P, T> {
+    prio: P,
+    value: T,
+    /// Length of the right spine.
+    rank: usize,
+    left: Heap<P, T>,
+    right: Heap<P, T>,
+}
+
+/// Persistent leftist max-heap.
+struct Heap<P, T> {
+    root: Option<Arc<Node<P, T>>>,
+}
+
+impl<P, T> Clone for Heap<P, T> {
+    fn clone(&self) -> Self {
+        Heap { root: self.root.clone() }
+    }
+}
+
+impl<P: Ord + Clone, T: Clone> Heap<P, T> {
+    fn new() -> Self {
+        Heap { root: None }
+    }
+
+    fn rank(&self) -> usize {
+        self.root.as_ref().map_or(0, |n| n.rank)
+    }
+
+    /// Create a node and put the child with the smaller rank to the right.
+    fn node(prio: P, value: T, a: Heap<P, T>, b: Heap<P, T>) -> Self {
+        let (left, right) = if a.rank() >= b.rank() { (a, b) } else { (b, a) };
+        Heap {
+            root: Some(Arc::new(Node {
+                prio,
+                value,
+                rank: right.rank() + 1,
+                left,
+                right,
+            })),
+        }
+    }
+
+    /// Merge two heaps. Only the right spines are copied.
+    fn merge(a: &Heap<P, T>, b: &Heap<P, T>) -> Heap<P, T> {
+        match (&a.root, &b.root) {
+            (None, _) => b.clone(),
+            (_, None) => a.clone(),
+            (Some(x), Some(y)) => {
+                let (top, other) = if x.prio >= y.prio { (x, b) } else { (y, a) };
+                let right = Heap::merge(&top.right, other);
+                Heap::node(top.prio.clone(), top.value.clone(), top.left.clone(), right)
+            }
+        }
+    }
+
+    fn push(&self, prio: P, value: T) -> Self {
+        Heap::merge(self, &Heap::node(prio, value, Heap::new(), Heap::new()))
+    }
+
+    /// Split the heap into the maximum and the remaining heap.
+    fn pop(&self) -> Option<((P, T), Heap<P, T>)> {
+        self.root.as_ref().map(|n| {
+            ((n.prio.clone(), n.value.clone()), Heap::merge(&n.left, &n.right))
+        })
+    }
+
+    /// Return all elements in unspecified order.
+    fn to_vec(&self) -> Vec<(P, T)> {
+        let mut out = Vec::new();
+        let mut stack: Vec<&Heap<P, T>> = vec![self];
+        while let Some(h) = stack.pop() {
+            if let Some(ref n) = h.root {
+                out.push((n.prio.clone(), n.value.clone()));
+                stack.push(&n.left);
+                stack.push(&n.right);
+            }
+        }
+        out
+    }
+}
+
+impl<P, T> Drop for Heap<P, T> {
+    /// Free the nodes without recursion. The left side of a leftist heap
+    /// may be as deep as the heap is large.
+    fn drop(&mut self) {
+        let mut stack = Vec::new();
+        stack.extend(self.root.take());
+        while let Some(n) = stack.pop() {
+            if let Ok(mut node) = Arc::try_unwrap(n) {
+                stack.extend(node.left.root.take());
+                stack.extend(node.right.root.take());
+            }
+        }
+    }
+}
+
+/// `PriorityQueue` is a threadsafe priority queue, that uses software transactional memory.
+///
+/// `pop` always returns the element with the highest priority. The order
+/// of elements with the same priority is unspecified.
+///
+///
+/// # Example
+///
+/// Wait until an element with at least priority 10 is available.
+///
+/// ```
+/// extern crate stm;
+/// extern crate stm_datastructures;
+///
+/// use stm::*;
+/// use stm_datastructures::PriorityQueue;
+///
+/// fn main() {
+///     let queue = PriorityQueue::new();
+///     atomically(|tx| {
+///         queue.push(tx, 1, "low")?;
+///         queue.push(tx, 10, "high")
+///     });
+///     let x = atomically(|tx| {
+///         let (prio, _) = queue.peek(tx)?;
+///         guard(prio >= 10)?;
+///         queue.pop(tx)
+///     });
+///     assert_eq!(x, (10, "high"));
+/// }
+/// ```
+#[derive(Clone)]
+pub struct PriorityQueue<P, T> {
+    heap: TVar<Heap<P, T>>,
+}
+
+impl<P, T> Default for PriorityQueue<P, T>
+where
+    P: Any + Sync + Send + Clone + Ord,
+    T: Any + Sync + Send + Clone,
+{
+    fn default() -> Self {
+        PriorityQueue::new()
+    }
+}
+
+impl<P, T> PriorityQueue<P, T>
+where
+    P: Any + Sync + Send + Clone + Ord,
+    T: Any + Sync + Send + Clone,
+{
+    /// Create a new, empty priority queue.
+    pub fn new() -> PriorityQueue<P, T> {
+        PriorityQueue { heap: TVar::new(Heap::new()) }
+    }
+
+    /// Add an element with priority `prio`.
+    pub fn push(&self, tx: &mut Transaction, prio: P, value: T) -> StmResult<()> {
+        self.heap.modify(tx, |h| h.push(prio, value))
+    }
+
+    /// Remove the element with the highest priority.
+    pub fn try_pop(&self, tx: &mut Transaction) -> StmResult<Option<(P, T)>> {
+        Ok(match self.heap.read(tx)?.pop() {
+            Some((x, rest)) => {
+                self.heap.write(tx, rest)?;
+                Some(x)
+            }
+            None => None,
+        })
+    }
+
+    /// Remove the element with the highest priority or retry if the queue is empty.
+    pub fn pop(&self, tx: &mut Transaction) -> StmResult<(P, T)> {
+        unwrap_or_retry(self.try_pop(tx)?)
+    }
+
+    /// Return the element with the highest priority without removing it.
+    pub fn try_peek(&self, tx: &mut Transaction) -> StmResult<Option<(P, T)>> {
+        let heap = self.heap.read(tx)?;
+        Ok(heap.root.as_ref().map(|n| (n.prio.clone(), n.value.clone())))
+    }
+
+    /// Return the element with the highest priority without removing it
+    /// or retry if the queue is empty.
+    pub fn peek(&self, tx: &mut Transaction) -> StmResult<(P, T)> {
+        unwrap_or_retry(self.try_peek(tx)?)
+    }
+
+    /// Check if the queue is empty.
+    pub fn is_empty(&self, tx: &mut Transaction) -> StmResult<bool> {
+        Ok(self.heap.read(tx)?.root.is_none())
+    }
+
+    /// Change the priority of an element equal to `value`.
+    ///
+    /// Return `false` if no such element exists. This rebuilds the whole
+    /// heap and therefore takes `O(n log n)` time.
+    pub fn change_priority(&self, tx: &mut Transaction, value: &T, prio: P) -> StmResult<bool>
+    where
+        T: PartialEq,
+    {
+        let mut elements = self.heap.read(tx)?.to_vec();
+        let found = match elements.iter_mut().find(|e| e.1 == *value) {
+            Some(e) => {
+                e.0 = prio;
+                true
+            }
+            None => false,
+        };
+        if found {
+            let heap = elements
+                .into_iter()
+                .fold(Heap::new(), |h, (p, v)| h.push(p, v));
+            self.heap.write(tx, heap)?;
+        }
+        Ok(found)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use stm::*;
+    use super::*;
+
+    /// Check if elements are returned by descending priority.
+    #[test]
+    fn pqueue_order() {
+        let queue = PriorityQueue::new();
+        let x = atomically(|tx| {
+            for &p in &[3, 1, 4, 1, 5, 9, 2, 6] {
+                queue.push(tx, p, p * 10)?;
+            }
+            let mut v = Vec::new();
+            while let Some((p, _)) = queue.try_pop(tx)? {
+                v.push(p);
+            }
+            Ok(v)
+        });
+        assert_eq!(vec![9, 6, 5, 4, 3, 2, 1, 1], x);
+    }
+
+    /// Check `change_priority` and `peek`.
+    #[test]
+    fn pqueue_change_priority() {
+        let queue = PriorityQueue::new();
+        atomically(|tx| {
+            queue.push(tx, 1, "a")?;
+            queue.push(tx, 2, "b")?;
+            queue.push(tx, 3, "c")
+        });
+
+        assert_eq!(Some((3, "c")), atomically(|tx| queue.try_peek(tx)));
+        assert!(atomically(|tx| queue.change_priority(tx, &"a", 10)));
+        assert!(!atomically(|tx| queue.change_priority(tx, &"z", 10)));
+        assert_eq!((10, "a"), atomically(|tx| queue.pop(tx)));
+        assert_eq!((3, "c"), atomically(|tx| queue.pop(tx)));
+    }
+
+    /// Degenerated heaps must not overflow the stack on destruction.
+    #[test]
+    fn pqueue_long() {
+        let queue = PriorityQueue::new();
+        atomically(|tx| {
+            for i in 0..100000 {
+                queue.push(tx, i, ())?;
+            }
+            Ok(())
+        });
+        assert_eq!((99999, ()), atomically(|tx| queue.pop(tx)));
+    }
+
+    /// A consumer blocks until an element with high priority arrives.
+    #[test]
+    fn pqueue_threaded_guard() {
+        use std::thread;
+        use std::time::Duration;
+        let queue = PriorityQueue::new();
+        let queue2 = queue.clone();
+
+        thread::spawn(move || for p in 0..10 {
+            thread::sleep(Duration::from_millis(2));
+            atomically(|tx| queue2.push(tx, p, ()));
+        });
+
+        let x = atomically(|tx| {
+            let (p, _) = queue.peek(tx)?;
+            guard(p == 9)?;
+            queue.pop(tx)
+        });
+        assert_eq!((9, ()), x);
+    }
+}
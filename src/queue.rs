@@ -1,11 +1,53 @@
 use stm::*;
 use std::any::Any;
+use std::error::Error;
+use std::fmt;
+use std::sync::{mpsc, Arc, Mutex, OnceLock};
+use std::thread;
+use std::time::Duration;
 use super::arclist::*;
+use super::raw;
+use super::timeout::Timeout;
+#[cfg(feature = "async")]
+use std::future::Future;
+#[cfg(feature = "async")]
+use super::future::atomically_async;
 
 // Queue is implemented using two lists (`read` and `write`).
 // `push` writes to the beginning of `write` and `pop` reads from the
 // beginning of `read`. If `read` is empty, the reversed list `write` is
 // used as a new list. This way all operations are amortized constant time.
+//
+// Both lists store their length, so that `len` is constant time without
+// introducing a variable, that is shared by readers and writers.
+//
+// `closed` is only read by `pop` when the queue is empty, so closing
+// support does not add conflicts between readers and writers.
+//
+// `channel` wraps a queue into sender and receiver handles. Each side shares
+// an `Arc`, whose strong count is the number of handles. Dropping the last
+// handle of a side closes the queue. Handles may be dropped inside of a
+// transaction, so this can not use `atomically`. Instead `close_now`
+// replaces the value of `closed` directly through `raw::try_write_now`.
+//
+// A handle may also be dropped as the old value of a variable, while a
+// commit holds its locks. Waiting for the lock of `closed` there could
+// deadlock with another commit, that holds it and waits for one of ours.
+// If `closed` is locked, the close is therefore left to a background
+// thread, which runs a normal transaction.
+
+/// Error returned when pushing to a closed queue or popping from a
+/// closed and empty queue.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Closed;
+
+impl fmt::Display for Closed {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("queue is closed")
+    }
+}
+
+impl Error for Closed {}
 
 /// `Queue` is a threadsafe FIFO queue, that uses software transactional memory.
 ///
@@ -24,35 +66,96 @@ use super::arclist::*;
 /// fn main() {
 ///     let queue = Queue::new();
 ///     let x = atomically(|tx| {
-///         queue.push(tx, 42)?;
+///         queue.push(tx, 42)?.unwrap();
 ///         queue.pop(tx)
 ///     });
-///     assert_eq!(x, 42);
+///     assert_eq!(x, Ok(42));
 /// }
 /// ```
 #[derive(Clone)]
 pub struct Queue<T> {
-    read: TVar<ArcList<T>>,
-    write: TVar<ArcList<T>>,
+    read: TVar<Half<T>>,
+    write: TVar<Half<T>>,
+    closed: TVar<bool>,
+}
+
+/// One of the two lists of a queue together with its length.
+struct Half<T> {
+    list: ArcList<T>,
+    len: usize,
+}
+
+impl<T> Clone for Half<T> {
+    fn clone(&self) -> Self {
+        Half {
+            list: self.list.clone(),
+            len: self.len,
+        }
+    }
+}
+
+impl<T: Clone> Half<T> {
+    fn new() -> Half<T> {
+        Half {
+            list: ArcList::new(),
+            len: 0,
+        }
+    }
+
+    fn prepend(self, value: T) -> Half<T> {
+        Half {
+            list: self.list.prepend(value),
+            len: self.len + 1,
+        }
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        let x = self.list.pop();
+        if x.is_some() {
+            self.len -= 1;
+        }
+        x
+    }
+
+    fn reverse(self) -> Half<T> {
+        Half {
+            list: self.list.reverse(),
+            len: self.len,
+        }
+    }
+}
+
+impl<T: Any + Sync + Clone + Send> Default for Queue<T> {
+    fn default() -> Self {
+        Queue::new()
+    }
 }
 
 impl<T: Any + Sync + Clone + Send> Queue<T> {
     /// Create a new queue.
     pub fn new() -> Queue<T> {
         Queue {
-            read: TVar::new(ArcList::new()),
-            write: TVar::new(ArcList::new()),
+            read: TVar::new(Half::new()),
+            write: TVar::new(Half::new()),
+            closed: TVar::new(false),
         }
     }
 
     /// Add a new element to the queue.
-    pub fn push(&self, tx: &mut Transaction, value: T) -> StmResult<()> {
-        self.write.modify(tx, |end| end.prepend(value))
+    ///
+    /// Fail if the queue is closed.
+    pub fn push(&self, tx: &mut Transaction, value: T) -> StmResult<Result<(), Closed>> {
+        if self.closed.read(tx)? {
+            return Ok(Err(Closed));
+        }
+        self.write.modify(tx, |end| end.prepend(value))?;
+        Ok(Ok(()))
     }
 
     /// Push a value to the front of the queue. Next call to `pop` will return `value`.
     ///
     /// `push_front` allows to undo pop-operations and operates the queue in a LIFO way.
+    /// It works even if the queue is closed.
     pub fn push_front(&self, tx: &mut Transaction, value: T) -> StmResult<()> {
         self.read.modify(tx, |end| end.prepend(value))
     }
@@ -67,42 +170,354 @@ impl<T: Any + Sync + Clone + Send> Queue<T> {
     }
 
     /// Return the first element without removing it.
-    pub fn peek(&self, tx: &mut Transaction) -> StmResult<T> {
+    ///
+    /// Retry if the queue is empty and fail if it is also closed.
+    pub fn peek(&self, tx: &mut Transaction) -> StmResult<Result<T, Closed>> {
         let v = self.pop(tx)?;
-        self.push_front(tx, v.clone())?;
+        if let Ok(ref e) = v {
+            self.push_front(tx, e.clone())?;
+        }
         Ok(v)
     }
 
     /// Remove an element from the queue.
     pub fn try_pop(&self, tx: &mut Transaction) -> StmResult<Option<T>> {
-        Ok(match self.read.read(tx)?.into_splitted() {
-            Some((x, xs)) => {
-                self.read.write(tx, xs)?;
-                Some(x)
-            }
+        let mut read = self.read.read(tx)?;
+        if let Some(x) = read.pop() {
+            self.read.write(tx, read)?;
+            return Ok(Some(x));
+        }
+
+        let write = self.write.read(tx)?;
+        if write.len == 0 {
+            return Ok(None);
+        }
+        self.write.write(tx, Half::new())?;
+        let mut read = write.reverse();
+        let x = read.pop();
+        self.read.write(tx, read)?;
+        Ok(x)
+    }
+
+    /// Remove an element from the queue.
+    ///
+    /// Retry if the queue is empty. Once the queue is closed and all
+    /// elements have been removed, fail instead of retrying.
+    pub fn pop(&self, tx: &mut Transaction) -> StmResult<Result<T, Closed>> {
+        match self.try_pop(tx)? {
+            Some(x) => Ok(Ok(x)),
             None => {
-                let write_list = self.write.replace(tx, ArcList::new())?;
-                match write_list.reverse().into_splitted() {
-                    None => None,
-                    Some((x, xs)) => {
-                        self.read.write(tx, xs.clone())?;
-                        Some(x)
-                    }
-                }
+                guard(self.closed.read(tx)?)?;
+                Ok(Err(Closed))
             }
-        })
+        }
     }
 
-    /// Remove an element from the queue.
-    pub fn pop(&self, tx: &mut Transaction) -> StmResult<T> {
-        unwrap_or_retry(self.try_pop(tx)?)
+    /// Add all elements of `iter` to the queue.
+    ///
+    /// The elements are added in iteration order with a single write.
+    /// Fail if the queue is closed.
+    pub fn push_all<I>(&self, tx: &mut Transaction, iter: I) -> StmResult<Result<(), Closed>>
+    where
+        I: IntoIterator<Item = T>,
+    {
+        if self.closed.read(tx)? {
+            return Ok(Err(Closed));
+        }
+        self.write.modify(tx, |end| iter.into_iter().fold(end, Half::prepend))?;
+        Ok(Ok(()))
+    }
+
+    /// Remove up to `n` elements from the queue.
+    ///
+    /// Return less than `n` elements if the queue does not contain enough.
+    pub fn pop_up_to(&self, tx: &mut Transaction, n: usize) -> StmResult<Vec<T>> {
+        let mut out = Vec::new();
+        let mut read = self.read.read(tx)?;
+        pop_into(&mut read, &mut out, n);
+        if out.len() < n {
+            let write = self.write.read(tx)?;
+            if write.len != 0 {
+                self.write.write(tx, Half::new())?;
+                read = write.reverse();
+                pop_into(&mut read, &mut out, n);
+            }
+        }
+        // Don't write anything if nothing changed, so that
+        // we don't cause conflicts with other transactions.
+        if !out.is_empty() {
+            self.read.write(tx, read)?;
+        }
+        Ok(out)
+    }
+
+    /// Remove exactly `n` elements from the queue or retry if there are less.
+    ///
+    /// Fail without removing anything, if the queue is closed and
+    /// can therefore never contain `n` elements.
+    pub fn pop_n(&self, tx: &mut Transaction, n: usize) -> StmResult<Result<Vec<T>, Closed>> {
+        if self.len(tx)? < n {
+            guard(self.closed.read(tx)?)?;
+            return Ok(Err(Closed));
+        }
+        Ok(Ok(self.pop_up_to(tx, n)?))
+    }
+
+    /// Remove all elements from the queue.
+    pub fn drain(&self, tx: &mut Transaction) -> StmResult<Vec<T>> {
+        self.pop_up_to(tx, usize::MAX)
+    }
+
+    /// Count the elements in the queue.
+    pub fn len(&self, tx: &mut Transaction) -> StmResult<usize> {
+        Ok(self.read.read(tx)?.len + self.write.read(tx)?.len)
     }
 
     /// Check if a queue is empty.
     pub fn is_empty(&self, tx: &mut Transaction) -> StmResult<bool> {
-        Ok(
-            self.read.read(tx)?.is_empty() || self.write.read(tx)?.is_empty(),
-        )
+        Ok(self.len(tx)? == 0)
+    }
+
+    /// Close the queue.
+    ///
+    /// Further pushes fail. Remaining elements can still be popped, after
+    /// that `pop` fails instead of blocking.
+    pub fn close(&self, tx: &mut Transaction) -> StmResult<()> {
+        self.closed.write(tx, true)
+    }
+
+    /// Check if the queue has been closed.
+    pub fn is_closed(&self, tx: &mut Transaction) -> StmResult<bool> {
+        self.closed.read(tx)
+    }
+
+    /// Add a value to the end of the queue in its own transaction.
+    ///
+    /// Like all `_now` and `_blocking` methods, this must not be called
+    /// inside of a transaction.
+    pub fn push_now(&self, value: T) -> Result<(), Closed> {
+        atomically(|tx| self.push(tx, value.clone()))
+    }
+
+    /// Remove the first element in its own transaction.
+    ///
+    /// Block while the queue is empty and fail once it is closed.
+    pub fn pop_blocking(&self) -> Result<T, Closed> {
+        atomically(|tx| self.pop(tx))
+    }
+
+    /// Remove the first element in its own transaction without blocking.
+    pub fn try_pop_now(&self) -> Option<T> {
+        atomically(|tx| self.try_pop(tx))
+    }
+
+    /// Remove the first element in its own transaction.
+    ///
+    /// Return `Ok(None)` if no element arrived within `timeout`. The
+    /// deadline is tracked by the timer thread shared by all `Timeout`s,
+    /// so no thread is started per call.
+    pub fn pop_timeout(&self, timeout: Duration) -> Result<Option<T>, Closed> {
+        let timeout = Timeout::after(timeout);
+        atomically(|tx| {
+            tx.or(
+                |tx| Ok(self.pop(tx)?.map(Some)),
+                |tx| timeout.wait(tx).map(|_| Ok(None)),
+            )
+        })
+    }
+
+    /// Remove the first element without blocking the thread.
+    ///
+    /// The returned future completes once an element is available or the
+    /// queue is closed.
+    #[cfg(feature = "async")]
+    pub fn pop_async(&self) -> impl Future<Output = Result<T, Closed>> {
+        let queue = self.clone();
+        atomically_async(move |tx| queue.pop(tx))
+    }
+
+    /// Close the queue outside of a transaction.
+    fn close_now(&self) {
+        if !raw::try_write_now(&self.closed, true) {
+            let queue = self.clone();
+            let job: CloseJob = Box::new(move || atomically(|tx| queue.close(tx)));
+            closer().lock().unwrap().send(job).unwrap();
+        }
+    }
+}
+
+/// A close deferred by `Queue::close_now`.
+type CloseJob = Box<dyn FnOnce() + Send>;
+
+/// Return the channel to the thread running deferred closes and start
+/// the thread if necessary.
+fn closer() -> &'static Mutex<mpsc::Sender<CloseJob>> {
+    static CLOSER: OnceLock<Mutex<mpsc::Sender<CloseJob>>> = OnceLock::new();
+    CLOSER.get_or_init(|| {
+        let (send, recv) = mpsc::channel::<CloseJob>();
+        thread::spawn(move || for job in recv {
+            job();
+        });
+        Mutex::new(send)
+    })
+}
+
+/// Create a queue with separate sending and receiving handles.
+///
+/// This is the transactional counterpart of `std::sync::mpsc::channel`.
+/// Both handles can be cloned. The queue is closed, when all senders or
+/// all receivers have been dropped. If a concurrent commit holds the lock
+/// of the queue at that moment, e.g. because the handle is dropped as the
+/// old value of a `TVar`, the queue is closed shortly after by a
+/// background thread instead.
+///
+///
+/// # Example
+///
+/// ```
+/// extern crate stm;
+/// extern crate stm_datastructures;
+///
+/// use stm::*;
+/// use stm_datastructures::queue::channel;
+///
+/// fn main() {
+///     let (sender, receiver) = channel();
+///     atomically(|tx| sender.push(tx, 42)).unwrap();
+///     drop(sender);
+///     assert_eq!(vec![42], receiver.collect::<Vec<_>>());
+/// }
+/// ```
+pub fn channel<T: Any + Sync + Clone + Send>() -> (TSender<T>, TReceiver<T>) {
+    let queue = Queue::new();
+    let sender = TSender { side: Arc::new(Side { queue: queue.clone() }) };
+    let receiver = TReceiver { side: Arc::new(Side { queue }) };
+    (sender, receiver)
+}
+
+/// Shared by all handles of one side of a channel.
+struct Side<T: Any + Sync + Clone + Send> {
+    queue: Queue<T>,
+}
+
+impl<T: Any + Sync + Clone + Send> Drop for Side<T> {
+    fn drop(&mut self) {
+        self.queue.close_now();
+    }
+}
+
+/// Sending half of a `channel`.
+#[derive(Clone)]
+pub struct TSender<T: Any + Sync + Clone + Send> {
+    side: Arc<Side<T>>,
+}
+
+/// Receiving half of a `channel`.
+///
+/// Iterating over a receiver pops one element per transaction and ends,
+/// when the channel is closed and empty.
+#[derive(Clone)]
+pub struct TReceiver<T: Any + Sync + Clone + Send> {
+    side: Arc<Side<T>>,
+}
+
+impl<T: Any + Sync + Clone + Send> TSender<T> {
+    /// Add a value to the end of the channel.
+    ///
+    /// Fail if all receivers have been dropped.
+    pub fn push(&self, tx: &mut Transaction, value: T) -> StmResult<Result<(), Closed>> {
+        self.side.queue.push(tx, value)
+    }
+
+    /// Add all values to the end of the channel.
+    pub fn push_all<I>(&self, tx: &mut Transaction, iter: I) -> StmResult<Result<(), Closed>>
+        where I: IntoIterator<Item = T>
+    {
+        self.side.queue.push_all(tx, iter)
+    }
+
+    /// Check if the channel has been closed.
+    pub fn is_closed(&self, tx: &mut Transaction) -> StmResult<bool> {
+        self.side.queue.is_closed(tx)
+    }
+
+    /// Return the number of sending handles.
+    pub fn sender_count(&self) -> usize {
+        Arc::strong_count(&self.side)
+    }
+}
+
+impl<T: Any + Sync + Clone + Send> TReceiver<T> {
+    /// Remove the first element without blocking.
+    pub fn try_pop(&self, tx: &mut Transaction) -> StmResult<Option<T>> {
+        self.side.queue.try_pop(tx)
+    }
+
+    /// Remove the first element.
+    ///
+    /// Retry if the channel is empty. Fail if it is also closed.
+    pub fn pop(&self, tx: &mut Transaction) -> StmResult<Result<T, Closed>> {
+        self.side.queue.pop(tx)
+    }
+
+    /// Return the first element without removing it.
+    pub fn try_peek(&self, tx: &mut Transaction) -> StmResult<Option<T>> {
+        self.side.queue.try_peek(tx)
+    }
+
+    /// Return the first element without removing it.
+    ///
+    /// Retry if the channel is empty. Fail if it is also closed.
+    pub fn peek(&self, tx: &mut Transaction) -> StmResult<Result<T, Closed>> {
+        self.side.queue.peek(tx)
+    }
+
+    /// Remove all pending elements.
+    pub fn drain(&self, tx: &mut Transaction) -> StmResult<Vec<T>> {
+        self.side.queue.drain(tx)
+    }
+
+    /// Return the number of pending elements.
+    pub fn len(&self, tx: &mut Transaction) -> StmResult<usize> {
+        self.side.queue.len(tx)
+    }
+
+    /// Check if there are no pending elements.
+    pub fn is_empty(&self, tx: &mut Transaction) -> StmResult<bool> {
+        self.side.queue.is_empty(tx)
+    }
+
+    /// Check if the channel has been closed.
+    pub fn is_closed(&self, tx: &mut Transaction) -> StmResult<bool> {
+        self.side.queue.is_closed(tx)
+    }
+
+    /// Return the number of receiving handles.
+    pub fn receiver_count(&self) -> usize {
+        Arc::strong_count(&self.side)
+    }
+}
+
+impl<T: Any + Sync + Clone + Send> Iterator for TReceiver<T> {
+    type Item = T;
+
+    /// Block until an element arrives and return `None` once the channel
+    /// is closed and empty.
+    ///
+    /// This runs a transaction and therefore must not be called inside
+    /// of a transaction.
+    fn next(&mut self) -> Option<T> {
+        atomically(|tx| self.pop(tx)).ok()
+    }
+}
+
+/// Pop elements from `list` into `out` until it holds `n` elements.
+fn pop_into<T: Clone>(list: &mut Half<T>, out: &mut Vec<T>, n: usize) {
+    while out.len() < n {
+        match list.pop() {
+            Some(x) => out.push(x),
+            None => return,
+        }
     }
 }
 
@@ -117,10 +532,10 @@ mod tests {
     fn channel_push_pop() {
         let queue = Queue::new();
         let x = atomically(|tx| {
-            queue.push(tx, 42)?;
+            queue.push(tx, 42)?.unwrap();
             queue.pop(tx)
         });
-        assert_eq!(42, x);
+        assert_eq!(Ok(42), x);
     }
 
     /// Check if the queue works as a FIFO within a single transaction.
@@ -128,12 +543,12 @@ mod tests {
     fn channel_order() {
         let queue = Queue::new();
         let x = atomically(|tx| {
-            queue.push(tx, 1)?;
-            queue.push(tx, 2)?;
-            queue.push(tx, 3)?;
-            let x1 = queue.pop(tx)?;
-            let x2 = queue.pop(tx)?;
-            let x3 = queue.pop(tx)?;
+            queue.push(tx, 1)?.unwrap();
+            queue.push(tx, 2)?.unwrap();
+            queue.push(tx, 3)?.unwrap();
+            let x1 = queue.pop(tx)?.unwrap();
+            let x2 = queue.pop(tx)?.unwrap();
+            let x3 = queue.pop(tx)?.unwrap();
             Ok((x1, x2, x3))
         });
         assert_eq!((1, 2, 3), x);
@@ -149,21 +564,201 @@ mod tests {
 
         // First push some values.
         atomically(|tx| {
-            queue2.push(tx, 1)?;
+            queue2.push(tx, 1)?.unwrap();
             queue2.push(tx, 2)
-        });
-        atomically(|tx| queue.push(tx, 3));
+        }).unwrap();
+        atomically(|tx| queue.push(tx, 3)).unwrap();
 
         // Get the values and check for consistency.
         let x = atomically(|tx| {
-            let x1 = queue.pop(tx)?;
-            let x2 = queue.pop(tx)?;
-            let x3 = queue.pop(tx)?;
+            let x1 = queue.pop(tx)?.unwrap();
+            let x2 = queue.pop(tx)?.unwrap();
+            let x3 = queue.pop(tx)?.unwrap();
             Ok((x1, x2, x3))
         });
         assert_eq!((1, 2, 3), x);
     }
 
+    /// Check the bulk operations.
+    #[test]
+    fn channel_bulk() {
+        let queue = Queue::new();
+        atomically(|tx| queue.push_all(tx, 0..5)).unwrap();
+        atomically(|tx| queue.push(tx, 5)).unwrap();
+        atomically(|tx| queue.push_all(tx, 6..10)).unwrap();
+
+        assert_eq!(10, atomically(|tx| queue.len(tx)));
+        assert_eq!(Ok(vec![0, 1, 2]), atomically(|tx| queue.pop_n(tx, 3)));
+        assert_eq!(vec![3, 4, 5, 6], atomically(|tx| queue.pop_up_to(tx, 4)));
+        assert_eq!(vec![7, 8, 9], atomically(|tx| queue.pop_up_to(tx, 4)));
+        assert!(atomically(|tx| queue.pop_up_to(tx, 4)).is_empty());
+
+        atomically(|tx| queue.push_all(tx, 0..3)).unwrap();
+        assert_eq!(vec![0, 1, 2], atomically(|tx| queue.drain(tx)));
+        assert_eq!(0, atomically(|tx| queue.len(tx)));
+    }
+
+    /// Check `is_empty` and `len`, while the elements are split between
+    /// both internal lists.
+    #[test]
+    fn channel_len_split() {
+        let queue = Queue::new();
+        assert!(atomically(|tx| queue.is_empty(tx)));
+
+        // Only the write list contains elements.
+        atomically(|tx| queue.push(tx, 1)).unwrap();
+        assert!(!atomically(|tx| queue.is_empty(tx)));
+        assert_eq!(1, atomically(|tx| queue.len(tx)));
+
+        // Move the elements into the read list and add one to the write list.
+        atomically(|tx| {
+            queue.push(tx, 2)?.unwrap();
+            queue.push(tx, 3)?.unwrap();
+            queue.pop(tx)?.unwrap();
+            queue.push(tx, 4)
+        }).unwrap();
+        assert_eq!(3, atomically(|tx| queue.len(tx)));
+
+        // Only the read list contains elements.
+        atomically(|tx| queue.push_front(tx, 0));
+        atomically(|tx| queue.pop_up_to(tx, 3));
+        assert_eq!(vec![4], atomically(|tx| queue.drain(tx)));
+
+        atomically(|tx| {
+            queue.push(tx, 5)?.unwrap();
+            queue.push(tx, 6)?.unwrap();
+            queue.pop(tx)
+        }).unwrap();
+        assert!(!atomically(|tx| queue.is_empty(tx)));
+        assert_eq!(1, atomically(|tx| queue.len(tx)));
+        atomically(|tx| queue.pop(tx)).unwrap();
+        assert!(atomically(|tx| queue.is_empty(tx)));
+    }
+
+    /// `pop_n` blocks until enough elements are available.
+    #[test]
+    fn channel_pop_n_threaded() {
+        use std::thread;
+        let queue = Queue::new();
+        let queue2 = queue.clone();
+
+        thread::spawn(move || for i in 0..10 {
+            atomically(|tx| queue2.push(tx, i)).unwrap();
+        });
+
+        assert_eq!(Ok((0..10).collect::<Vec<_>>()), atomically(|tx| queue.pop_n(tx, 10)));
+    }
+
+    /// A closed queue rejects pushes, but can still be drained.
+    #[test]
+    fn channel_close() {
+        let queue = Queue::new();
+        atomically(|tx| {
+            queue.push(tx, 1)?.unwrap();
+            queue.push(tx, 2)?.unwrap();
+            queue.close(tx)
+        });
+
+        assert!(atomically(|tx| queue.is_closed(tx)));
+        assert_eq!(Err(Closed), atomically(|tx| queue.push(tx, 3)));
+        assert_eq!(Err(Closed), atomically(|tx| queue.pop_n(tx, 3)));
+        assert_eq!(Ok(1), atomically(|tx| queue.pop(tx)));
+        assert_eq!(Ok(2), atomically(|tx| queue.peek(tx)));
+        assert_eq!(Ok(2), atomically(|tx| queue.pop(tx)));
+        assert_eq!(Err(Closed), atomically(|tx| queue.pop(tx)));
+        assert_eq!(Err(Closed), atomically(|tx| queue.peek(tx)));
+    }
+
+    /// Closing the queue wakes up a blocked consumer.
+    #[test]
+    fn channel_close_threaded() {
+        use std::thread;
+        use std::time::Duration;
+        let queue = Queue::<i32>::new();
+        let queue2 = queue.clone();
+
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            atomically(|tx| queue2.close(tx));
+        });
+
+        assert_eq!(Err(Closed), atomically(|tx| queue.pop(tx)));
+    }
+
+    /// Test the wrappers, that run their own transactions.
+    #[test]
+    fn channel_now() {
+        use std::time::Duration;
+        let queue = Queue::new();
+        queue.push_now(1).unwrap();
+        queue.push_now(2).unwrap();
+        assert_eq!(Ok(1), queue.pop_blocking());
+        assert_eq!(Some(2), queue.try_pop_now());
+        assert_eq!(None, queue.try_pop_now());
+        assert_eq!(Ok(None), queue.pop_timeout(Duration::from_millis(10)));
+
+        queue.push_now(3).unwrap();
+        assert_eq!(Ok(Some(3)), queue.pop_timeout(Duration::from_secs(10)));
+        atomically(|tx| queue.close(tx));
+        assert_eq!(Err(Closed), queue.push_now(4));
+        assert_eq!(Err(Closed), queue.pop_timeout(Duration::from_secs(10)));
+    }
+
+    /// Dropping the last sender closes the channel and ends the iteration.
+    #[test]
+    fn channel_split_threaded() {
+        use std::thread;
+        let (sender, receiver) = channel();
+
+        let senders: Vec<_> = (0..4).map(|_| sender.clone()).collect();
+        assert_eq!(5, sender.sender_count());
+        drop(sender);
+        for (i, sender) in senders.into_iter().enumerate() {
+            thread::spawn(move || for j in 0..10 {
+                atomically(|tx| sender.push(tx, i * 10 + j)).unwrap();
+            });
+        }
+
+        let mut all: Vec<_> = receiver.collect();
+        all.sort();
+        assert_eq!((0..40).collect::<Vec<_>>(), all);
+    }
+
+    /// Dropping the last handle while the queue is locked by a commit
+    /// closes it later instead of blocking.
+    #[test]
+    fn channel_split_deferred_close() {
+        let (sender, receiver) = channel::<i32>();
+        let queue = receiver.side.queue.clone();
+        {
+            // Stands in for a concurrent commit, that has read `closed`.
+            let _lock = queue.closed.control_block().value.read().unwrap();
+            drop(sender);
+        }
+        assert_eq!(Err(Closed), atomically(|tx| receiver.pop(tx)));
+    }
+
+    /// Dropping all receivers makes pushes fail.
+    #[test]
+    fn channel_split_receiver_drop() {
+        let (sender, receiver) = channel();
+        let receiver2 = receiver.clone();
+        assert_eq!(2, receiver.receiver_count());
+
+        atomically(|tx| sender.push(tx, 1)).unwrap();
+        drop(receiver);
+        assert!(!atomically(|tx| sender.is_closed(tx)));
+        assert_eq!(Ok(1), atomically(|tx| receiver2.pop(tx)));
+
+        // Dropping inside of a transaction must not start another one.
+        atomically(|tx| {
+            drop(receiver2.clone());
+            sender.is_closed(tx)
+        });
+        drop(receiver2);
+        assert_eq!(Err(Closed), atomically(|tx| sender.push(tx, 2)));
+    }
+
     /// Test if the queue works with multiple concurrent threads.
     #[test]
     fn channel_threaded() {
@@ -175,8 +770,8 @@ mod tests {
         for i in 0..10 {
             let queue2 = queue.clone();
             thread::spawn(move || {
-                thread::sleep(Duration::from_millis(20 as u64));
-                atomically(|tx| queue2.push(tx, i));
+                thread::sleep(Duration::from_millis(20));
+                atomically(|tx| queue2.push(tx, i)).unwrap();
             });
         }
 
@@ -184,7 +779,7 @@ mod tests {
         let mut v = atomically(|tx| {
             let mut v = Vec::new();
             for _ in 0..10 {
-                v.push(queue.pop(tx)?);
+                v.push(queue.pop(tx)?.unwrap());
             }
             Ok(v)
         });
@@ -192,8 +787,6 @@ mod tests {
         // We don't know the order, but want to check if we received everything
         // correcty.
         v.sort();
-        for i in 0..10 {
-            assert_eq!(v[i], i);
-        }
+        assert_eq!((0..10).collect::<Vec<_>>(), v);
     }
 }
@@ -0,0 +1,57 @@
+use stm::*;
+use std::any::Any;
+use std::mem;
+use std::sync::Arc;
+
+// Access to the value of a `TVar` outside of a transaction.
+//
+// `stm` only offers this through `TVar::control_block`, which exposes the
+// internal representation of a variable. All code, that relies on it,
+// lives in this module. Check these functions against `stm/src/var.rs` and
+// `stm/src/transaction/mod.rs` before updating `stm`.
+//
+// A variable stores its value as `Arc<dyn Any>` behind a `RwLock`. A commit
+// takes the write lock of every variable it writes and the read lock of
+// every variable it only read, and checks that the stored `Arc` is still
+// the one it has read. It holds all these locks until its writes are done.
+// Replacing the value under the write lock therefore happens either before
+// such a check, which then fails, or after the commit, just like a commit
+// of another transaction.
+//
+// The old values are dropped during the write-back, while the commit still
+// holds its locks. `Drop` implementations, that end up here, must therefore
+// never block on a lock.
+
+/// Replace the value of `var` and wake up all threads waiting on it.
+///
+/// Return `false` without blocking and leave `var` untouched, if it is
+/// locked by a commit. Only this makes it usable from `Drop`, which may
+/// run inside of a transaction or while a commit holds its locks.
+pub fn try_write_now<T: Any + Sync + Send + Clone>(var: &TVar<T>, value: T) -> bool {
+    let ctrl = var.control_block();
+    match ctrl.value.try_write() {
+        Ok(mut lock) => *lock = Arc::new(value),
+        Err(_) => return false,
+    }
+    ctrl.wake_all();
+    true
+}
+
+/// Move the value out of `var`, if nothing else can reach it anymore.
+///
+/// Transactions and other clones of `var` share its control block, so a
+/// count of one means, that `var` is about to be dropped. Nobody else can
+/// hold its lock then, so taking it never blocks. The value is replaced
+/// by `T::default()`.
+pub fn take_unique<T>(var: &TVar<T>) -> Option<T>
+where
+    T: Any + Sync + Send + Clone + Default,
+{
+    let ctrl = var.control_block();
+    if Arc::strong_count(ctrl) != 1 {
+        return None;
+    }
+    let value = mem::replace(&mut *ctrl.value.write().unwrap(), Arc::new(T::default()));
+    let value = value.downcast::<T>().ok()?;
+    Arc::try_unwrap(value).ok()
+}
@@ -0,0 +1,177 @@
+use stm::*;
+use std::any::Any;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use super::{Queue, BoundedQueue, TMVar, TReceiver, BroadcastReceiver, Closed};
+
+// `select` chains the sources with `or`. If all of them retry, the
+// transaction blocks on the variables read by every source and wakes up
+// as soon as any of them changes.
+//
+// `or` prefers its first branch, so the first source would always win
+// when several are ready. Every call therefore starts at the next index
+// of a global counter.
+
+/// Rotates the first source to try.
+static NEXT_START: AtomicUsize = AtomicUsize::new(0);
+
+/// A source of values, that can be used with `select`.
+pub trait Receive<T> {
+    /// Remove the next value or retry if there is none.
+    ///
+    /// Fail if there will never be any value again.
+    fn receive(&self, tx: &mut Transaction) -> StmResult<Result<T, Closed>>;
+}
+
+/// Remove a value from the first source, that has one.
+///
+/// Return the index of the source and the removed value. Retry if all
+/// sources are empty. A closed and empty source is selected with
+/// `Err(Closed)`.
+///
+/// When several sources are ready, the choice rotates between calls,
+/// so that no source is starved.
+///
+/// # Panics
+///
+/// Panics if `sources` is empty, because the transaction would block forever.
+///
+///
+/// # Example
+///
+/// ```
+/// extern crate stm;
+/// extern crate stm_datastructures;
+///
+/// use stm::*;
+/// use stm_datastructures::{Queue, TMVar};
+/// use stm_datastructures::select::select;
+///
+/// fn main() {
+///     let queue = Queue::new();
+///     let var = TMVar::new(42);
+///     let x = atomically(|tx| select(tx, &[&queue, &var]));
+///     assert_eq!(x, (1, Ok(42)));
+/// }
+/// ```
+pub fn select<T>(tx: &mut Transaction, sources: &[&dyn Receive<T>]) -> StmResult<(usize, Result<T, Closed>)> {
+    assert!(!sources.is_empty(), "select needs at least one source");
+    let start = NEXT_START.fetch_add(1, Ordering::Relaxed) % sources.len();
+    let order: Vec<usize> = (start..sources.len()).chain(0..start).collect();
+    select_in_order(tx, sources, &order)
+}
+
+fn select_in_order<T>(tx: &mut Transaction, sources: &[&dyn Receive<T>], order: &[usize])
+    -> StmResult<(usize, Result<T, Closed>)>
+{
+    match order.split_first() {
+        None => retry(),
+        Some((&i, rest)) => tx.or(
+            |tx| Ok((i, sources[i].receive(tx)?)),
+            |tx| select_in_order(tx, sources, rest),
+        ),
+    }
+}
+
+impl<T: Any + Sync + Clone + Send> Receive<T> for Queue<T> {
+    fn receive(&self, tx: &mut Transaction) -> StmResult<Result<T, Closed>> {
+        self.pop(tx)
+    }
+}
+
+impl<T: Any + Sync + Clone + Send> Receive<T> for BoundedQueue<T> {
+    fn receive(&self, tx: &mut Transaction) -> StmResult<Result<T, Closed>> {
+        self.pop(tx)
+    }
+}
+
+impl<T: Any + Sync + Clone + Send> Receive<T> for TReceiver<T> {
+    fn receive(&self, tx: &mut Transaction) -> StmResult<Result<T, Closed>> {
+        self.pop(tx)
+    }
+}
+
+impl<T: Any + Sync + Clone + Send> Receive<T> for TMVar<T> {
+    fn receive(&self, tx: &mut Transaction) -> StmResult<Result<T, Closed>> {
+        self.take(tx).map(Ok)
+    }
+}
+
+impl<T: Any + Sync + Clone + Send> Receive<T> for BroadcastReceiver<T> {
+    fn receive(&self, tx: &mut Transaction) -> StmResult<Result<T, Closed>> {
+        self.pop(tx).map(Ok)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use stm::*;
+    use super::*;
+
+    /// Select from the sources, that are ready.
+    #[test]
+    fn select_ready() {
+        let queue = Queue::new();
+        let bqueue = BoundedQueue::new(2);
+        let var = TMVar::new_empty();
+        atomically(|tx| {
+            queue.push(tx, 1)?.unwrap();
+            bqueue.push(tx, 2)?.unwrap();
+            var.put(tx, 3)
+        });
+
+        let mut got = Vec::new();
+        for _ in 0..3 {
+            let (i, x) = atomically(|tx| select(tx, &[&queue, &bqueue, &var]));
+            assert_eq!(Ok(i as i32 + 1), x);
+            got.push(i);
+        }
+        got.sort();
+        assert_eq!(vec![0, 1, 2], got);
+
+        atomically(|tx| bqueue.close(tx));
+        assert_eq!((1, Err(Closed)), atomically(|tx| select(tx, &[&queue, &bqueue, &var])));
+    }
+
+    /// No source is starved, if all of them are always ready.
+    #[test]
+    fn select_fair() {
+        let queues: Vec<Queue<usize>> = (0..3).map(|_| Queue::new()).collect();
+        let mut counts = [0; 3];
+        for _ in 0..30 {
+            let (i, _) = atomically(|tx| {
+                for (i, q) in queues.iter().enumerate() {
+                    q.push(tx, i)?.unwrap();
+                }
+                select(tx, &[&queues[0], &queues[1], &queues[2]])
+            });
+            counts[i] += 1;
+        }
+        assert!(counts.iter().all(|&c| c > 0));
+    }
+
+    /// Selecting from no sources at all is a bug.
+    #[test]
+    #[should_panic(expected = "select needs at least one source")]
+    fn select_empty() {
+        let sources: [&dyn Receive<i32>; 0] = [];
+        let _ = atomically(|tx| select(tx, &sources));
+    }
+
+    /// Block until one of the sources gets a value from another thread.
+    #[test]
+    fn select_threaded() {
+        use std::thread;
+        use std::time::Duration;
+        let q1: Queue<i32> = Queue::new();
+        let q2 = Queue::new();
+
+        let q2b = q2.clone();
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            q2b.push_now(42).unwrap();
+        });
+
+        assert_eq!((1, Ok(42)), atomically(|tx| select(tx, &[&q1, &q2])));
+    }
+}
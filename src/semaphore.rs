@@ -1,4 +1,10 @@
 use stm::*;
+use std::time::Duration;
+use super::timeout::Timeout;
+#[cfg(feature = "async")]
+use std::future::Future;
+#[cfg(feature = "async")]
+use super::future::atomically_async;
 
 /// `Semaphore` is an implementation of semaphores on top of software transactional
 /// memory.
@@ -9,24 +15,208 @@ use stm::*;
 pub struct Semaphore {
     /// Semaphores are internally just a number.
     num: TVar<u32>,
+
+    /// The maximal number of tokens.
+    max: u32,
 }
 
 impl Semaphore {
     /// Create a new semaphore with `n` initial tokens.
     pub fn new(n: u32) -> Semaphore {
-        Semaphore { num: TVar::new(n) }
+        Semaphore::with_max(n, u32::MAX)
+    }
+
+    /// Create a new semaphore with `n` initial tokens, that never
+    /// holds more than `max` tokens.
+    pub fn with_max(n: u32, max: u32) -> Semaphore {
+        assert!(n <= max, "initial tokens exceed the maximum");
+        Semaphore {
+            num: TVar::new(n),
+            max,
+        }
     }
 
     /// Take a token from the semaphore or retry if none left.
     pub fn wait(&self, tx: &mut Transaction) -> StmResult<()> {
-        let n = self.num.read(tx)?;
-        guard(n != 0)?;
-        self.num.write(tx, n - 1)
+        self.acquire_many(tx, 1)
     }
 
     /// Free a token.
+    ///
+    /// Retry if the semaphore already holds the maximal number of tokens.
     pub fn signal(&self, tx: &mut Transaction) -> StmResult<()> {
-        self.num.modify(tx, |n| n + 1)
+        self.release_many(tx, 1)
+    }
+
+    /// Take a token in its own transaction and block until one is available.
+    ///
+    /// This must not be called inside of a transaction.
+    pub fn wait_blocking(&self) {
+        atomically(|tx| self.wait(tx))
+    }
+
+    /// Take a token in its own transaction.
+    ///
+    /// Give up and return `false` if no token became available within
+    /// `timeout`. The deadline is handled by a `Timeout`.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        let timeout = Timeout::after(timeout);
+        atomically(|tx| {
+            tx.or(
+                |tx| self.wait(tx).map(|_| true),
+                |tx| timeout.wait(tx).map(|_| false),
+            )
+        })
+    }
+
+    /// Free a token in its own transaction.
+    ///
+    /// This must not be called inside of a transaction.
+    pub fn signal_now(&self) {
+        atomically(|tx| self.signal(tx))
+    }
+
+    /// Take a token without blocking the thread.
+    ///
+    /// The returned future completes once a token has been taken.
+    #[cfg(feature = "async")]
+    pub fn acquire_async(&self) -> impl Future<Output = ()> {
+        let sem = self.clone();
+        atomically_async(move |tx| sem.wait(tx))
+    }
+
+    /// Take `n` tokens at once or retry until enough are available.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` exceeds the maximum, because the semaphore could
+    /// never hold enough tokens.
+    pub fn acquire_many(&self, tx: &mut Transaction, n: u32) -> StmResult<()> {
+        assert!(n <= self.max, "acquire_many exceeds the maximum of the semaphore");
+        let num = self.num.read(tx)?;
+        guard(num >= n)?;
+        self.num.write(tx, num - n)
+    }
+
+    /// Free `n` tokens at once.
+    ///
+    /// Retry until the new tokens fit below the maximum.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `n` exceeds the maximum, because the tokens could
+    /// never fit.
+    pub fn release_many(&self, tx: &mut Transaction, n: u32) -> StmResult<()> {
+        assert!(n <= self.max, "release_many exceeds the maximum of the semaphore");
+        let num = self.num.read(tx)?;
+        guard(self.max - num >= n)?;
+        self.num.write(tx, num + n)
+    }
+
+    /// Take a token if one is available.
+    ///
+    /// Return `false` instead of retrying.
+    pub fn try_acquire(&self, tx: &mut Transaction) -> StmResult<bool> {
+        self.try_acquire_many(tx, 1)
+    }
+
+    /// Take `n` tokens if enough are available.
+    ///
+    /// Return `false` instead of retrying.
+    pub fn try_acquire_many(&self, tx: &mut Transaction, n: u32) -> StmResult<bool> {
+        let num = self.num.read(tx)?;
+        if num < n {
+            return Ok(false);
+        }
+        self.num.write(tx, num - n)?;
+        Ok(true)
+    }
+
+    /// Return the number of available tokens.
+    pub fn available(&self, tx: &mut Transaction) -> StmResult<u32> {
+        self.num.read(tx)
+    }
+
+    /// Return the maximal number of tokens.
+    pub fn max(&self) -> u32 {
+        self.max
+    }
+
+    /// Take a token outside of a transaction.
+    ///
+    /// The token is freed, when the returned guard is dropped.
+    /// The guard must not be dropped inside of a transaction, because
+    /// transactions can not be nested.
+    pub fn acquire_guard(&self) -> SemaphoreGuard {
+        self.acquire_many_guard(1)
+    }
+
+    /// Take `n` tokens outside of a transaction.
+    ///
+    /// See `acquire_guard`.
+    pub fn acquire_many_guard(&self, n: u32) -> SemaphoreGuard {
+        atomically(|tx| self.acquire_many(tx, n));
+        SemaphoreGuard {
+            sem: self.clone(),
+            n,
+            released: TVar::new(false),
+        }
+    }
+
+    /// Return `n` tokens, that have been taken before, and drop the
+    /// tokens, that exceed the maximum.
+    fn give_back(&self, tx: &mut Transaction, n: u32) -> StmResult<()> {
+        let num = self.num.read(tx)?;
+        self.num.write(tx, num.saturating_add(n).min(self.max))
+    }
+}
+
+/// Tokens taken by `Semaphore::acquire_guard`.
+///
+/// The tokens are returned to the semaphore on drop, unless `release`
+/// has already returned them. Returning tokens never retries. If the
+/// semaphore has been filled up to its maximum in the meantime, the
+/// tokens, that don't fit anymore, are dropped.
+///
+/// # Panics
+///
+/// Dropping the guard runs its own transaction, so it panics, if the guard
+/// is dropped inside of a transaction. Inside of a transaction, e.g. on an
+/// early return with `?`, call `release` first. A drop while unwinding out
+/// of a transaction aborts the process.
+#[must_use = "the tokens are returned immediately if the guard is not used"]
+pub struct SemaphoreGuard {
+    sem: Semaphore,
+    n: u32,
+
+    /// Set by `release`. Transactions may run several times, so this must
+    /// be a variable and not a plain flag.
+    released: TVar<bool>,
+}
+
+impl SemaphoreGuard {
+    /// Return the number of tokens held by this guard.
+    pub fn tokens(&self) -> u32 {
+        self.n
+    }
+
+    /// Return the tokens inside of a transaction.
+    ///
+    /// This takes `&self`, because a transaction can't consume a value,
+    /// that it may need again, when it runs another time. After the
+    /// transaction has committed, dropping the guard does nothing.
+    pub fn release(&self, tx: &mut Transaction) -> StmResult<()> {
+        if self.released.read(tx)? {
+            return Ok(());
+        }
+        self.released.write(tx, true)?;
+        self.sem.give_back(tx, self.n)
+    }
+}
+
+impl Drop for SemaphoreGuard {
+    fn drop(&mut self) {
+        atomically(|tx| self.release(tx));
     }
 }
 
@@ -51,6 +241,95 @@ mod tests {
         });
     }
 
+    /// Test taking and freeing multiple tokens at once.
+    #[test]
+    fn sem_many() {
+        let sem = Semaphore::new(5);
+        atomically(|tx| {
+            sem.acquire_many(tx, 3)?;
+            assert_eq!(2, sem.available(tx)?);
+            assert!(!sem.try_acquire_many(tx, 3)?);
+            assert!(sem.try_acquire(tx)?);
+            sem.release_many(tx, 4)?;
+            assert_eq!(5, sem.available(tx)?);
+            Ok(())
+        });
+    }
+
+    /// Releasing above the maximum blocks until a token is taken.
+    #[test]
+    fn sem_max() {
+        use std::thread;
+        use std::time::Duration;
+
+        let sem = Semaphore::with_max(2, 2);
+        let sem2 = sem.clone();
+
+        thread::spawn(move || {
+            thread::sleep(Duration::from_millis(20));
+            atomically(|tx| sem2.wait(tx));
+        });
+
+        atomically(|tx| sem.signal(tx));
+        assert_eq!(2, atomically(|tx| sem.available(tx)));
+    }
+
+    /// More tokens than the maximum can never be taken.
+    #[test]
+    #[should_panic(expected = "acquire_many exceeds the maximum")]
+    fn sem_acquire_too_many() {
+        let sem = Semaphore::with_max(2, 2);
+        atomically(|tx| sem.acquire_many(tx, 3));
+    }
+
+    /// Test the wrappers, that run their own transactions.
+    #[test]
+    fn sem_now() {
+        use std::thread;
+        let sem = Semaphore::new(0);
+        let sem2 = sem.clone();
+        thread::spawn(move || sem2.signal_now());
+        sem.wait_blocking();
+        assert_eq!(0, atomically(|tx| sem.available(tx)));
+        assert!(!sem.wait_timeout(Duration::from_millis(10)));
+        sem.signal_now();
+        assert!(sem.wait_timeout(Duration::from_secs(10)));
+    }
+
+    /// Test if the guard returns its tokens on drop.
+    #[test]
+    fn sem_guard() {
+        let sem = Semaphore::new(3);
+        {
+            let g = sem.acquire_many_guard(2);
+            assert_eq!(2, g.tokens());
+            let _g2 = sem.acquire_guard();
+            assert_eq!(0, atomically(|tx| sem.available(tx)));
+        }
+        assert_eq!(3, atomically(|tx| sem.available(tx)));
+    }
+
+    /// Releasing a guard in a transaction returns the tokens only once and
+    /// returning them never blocks.
+    #[test]
+    fn sem_guard_release() {
+        let sem = Semaphore::with_max(2, 2);
+        let g = sem.acquire_many_guard(2);
+        atomically(|tx| {
+            g.release(tx)?;
+            g.release(tx)
+        });
+        assert_eq!(2, atomically(|tx| sem.available(tx)));
+        drop(g);
+        assert_eq!(2, atomically(|tx| sem.available(tx)));
+
+        // Somebody else fills the semaphore, while the guard is held.
+        let g = sem.acquire_guard();
+        atomically(|tx| sem.signal(tx));
+        drop(g);
+        assert_eq!(2, atomically(|tx| sem.available(tx)));
+    }
+
     /// Test if the semaphore can be used to synchronize two threads.
     #[test]
     fn sem_threaded() {
@@ -0,0 +1,149 @@
+use stm::*;
+use std::any::Any;
+use std::iter::FromIterator;
+use std::sync::Arc;
+
+/// `TArray` is a threadsafe array of fixed length, where each slot is its
+/// own transactional variable.
+///
+/// Transactions, that touch different indices, don't conflict with each
+/// other. This is the main difference to a `Vec` inside of a single `TVar`.
+///
+/// Accessing an index out of bounds panics like a slice does.
+///
+///
+/// # Example
+///
+/// ```
+/// extern crate stm;
+/// extern crate stm_datastructures;
+///
+/// use stm::*;
+/// use stm_datastructures::TArray;
+///
+/// fn main() {
+///     let array = TArray::new(vec![1, 2, 3]);
+///     let x = atomically(|tx| {
+///         array.modify(tx, 0, |x| x * 10)?;
+///         array.swap(tx, 0, 2)?;
+///         array.snapshot(tx)
+///     });
+///     assert_eq!(x, vec![3, 2, 10]);
+/// }
+/// ```
+#[derive(Clone)]
+pub struct TArray<T> {
+    slots: Arc<Vec<TVar<T>>>,
+}
+
+impl<T: Any + Sync + Clone + Send> TArray<T> {
+    /// Create an array holding `values`.
+    pub fn new(values: Vec<T>) -> TArray<T> {
+        values.into_iter().collect()
+    }
+
+    /// Create an array of length `len`, where every slot holds `value`.
+    pub fn with_len(len: usize, value: T) -> TArray<T> {
+        TArray::new(vec![value; len])
+    }
+
+    /// Return the number of slots.
+    pub fn len(&self) -> usize {
+        self.slots.len()
+    }
+
+    /// Check if the array has no slots.
+    pub fn is_empty(&self) -> bool {
+        self.slots.is_empty()
+    }
+
+    /// Return the value at `index`.
+    pub fn read(&self, tx: &mut Transaction, index: usize) -> StmResult<T> {
+        self.slots[index].read(tx)
+    }
+
+    /// Replace the value at `index`.
+    pub fn write(&self, tx: &mut Transaction, index: usize, value: T) -> StmResult<()> {
+        self.slots[index].write(tx, value)
+    }
+
+    /// Apply `f` to the value at `index`.
+    pub fn modify<F>(&self, tx: &mut Transaction, index: usize, f: F) -> StmResult<()>
+    where
+        F: FnOnce(T) -> T,
+    {
+        self.slots[index].modify(tx, f)
+    }
+
+    /// Exchange the values at `i` and `j`.
+    pub fn swap(&self, tx: &mut Transaction, i: usize, j: usize) -> StmResult<()> {
+        let a = self.slots[i].read(tx)?;
+        if i == j {
+            return Ok(());
+        }
+        let b = self.slots[j].replace(tx, a)?;
+        self.slots[i].write(tx, b)
+    }
+
+    /// Return all values.
+    ///
+    /// This reads every slot and therefore conflicts with all writes.
+    pub fn snapshot(&self, tx: &mut Transaction) -> StmResult<Vec<T>> {
+        self.slots.iter().map(|slot| slot.read(tx)).collect()
+    }
+}
+
+impl<T: Any + Sync + Clone + Send> FromIterator<T> for TArray<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        TArray { slots: Arc::new(iter.into_iter().map(TVar::new).collect()) }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use stm::*;
+    use super::*;
+
+    /// Check the single slot operations.
+    #[test]
+    fn tarray_ops() {
+        let array = TArray::with_len(4, 0);
+        assert_eq!(4, array.len());
+        atomically(|tx| {
+            array.write(tx, 1, 5)?;
+            array.modify(tx, 2, |x| x + 7)?;
+            array.swap(tx, 1, 3)?;
+            array.swap(tx, 2, 2)
+        });
+        assert_eq!(vec![0, 0, 7, 5], atomically(|tx| array.snapshot(tx)));
+        assert_eq!(7, atomically(|tx| array.read(tx, 2)));
+    }
+
+    /// Indices out of bounds panic.
+    #[test]
+    #[should_panic]
+    fn tarray_out_of_bounds() {
+        let array = TArray::new(vec![1]);
+        atomically(|tx| array.read(tx, 1));
+    }
+
+    /// Threads, that update distinct slots, don't lose updates.
+    #[test]
+    fn tarray_threaded() {
+        use std::thread;
+        let array = TArray::with_len(4, 0);
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let array = array.clone();
+                thread::spawn(move || for _ in 0..100 {
+                    atomically(|tx| array.modify(tx, i, |x| x + 1));
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        assert_eq!(vec![100; 4], atomically(|tx| array.snapshot(tx)));
+    }
+}
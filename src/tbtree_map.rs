@@ -0,0 +1,513 @@
+use stm::*;
+use std::any::Any;
+use std::mem;
+use std::ops::Bound;
+use std::ops::RangeBounds;
+use std::sync::Arc;
+
+// `TBTreeMap` is a B+ tree. Every node is stored in its own `TVar`.
+// Values are only stored in the leaves, inner nodes just contain
+// separator keys.
+//
+// Inserting or removing a key reads the path from the root to a leaf,
+// but only writes the leaf itself. Inner nodes are only written if a
+// leaf is split or removed. Therefore transactions on distant keys
+// usually don't conflict.
+//
+// Nodes are not merged when they become small. Empty nodes are removed
+// from their parent, so that the tree never contains empty subtrees.
+
+/// Maximal number of entries of a leaf and children of an inner node.
+const MAX_CHILDREN: usize = 32;
+
+#[derive(Clone)]
+enum Node<K, V> {
+    /// Sorted entries.
+    Leaf(Vec<(K, V)>),
+
+    /// Separator keys and children.
+    ///
+    /// Child `i` contains all keys in `keys[i-1]..keys[i]`.
+    /// There is always one more child than keys.
+    Inner(Vec<K>, Vec<NodeVar<K, V>>),
+}
+
+type NodeVar<K, V> = TVar<Arc<Node<K, V>>>;
+
+/// Result of inserting into a subtree: the old value and the new right
+/// sibling with its separator key, if the node has been split.
+type InsertResult<K, V> = (Option<V>, Option<(K, NodeVar<K, V>)>);
+
+/// `TBTreeMap` is a threadsafe ordered map, that uses software transactional memory.
+///
+/// Every node of the tree is a separate `TVar`, so that transactions on
+/// distant keys don't conflict.
+///
+///
+/// # Example
+///
+/// ```
+/// extern crate stm;
+/// extern crate stm_datastructures;
+///
+/// use stm::*;
+/// use stm_datastructures::TBTreeMap;
+///
+/// fn main() {
+///     let map = TBTreeMap::new();
+///     let x = atomically(|tx| {
+///         map.insert(tx, 3, "c")?;
+///         map.insert(tx, 1, "a")?;
+///         map.insert(tx, 2, "b")?;
+///         map.range(tx, 2..)
+///     });
+///     assert_eq!(x, vec![(2, "b"), (3, "c")]);
+/// }
+/// ```
+#[derive(Clone)]
+pub struct TBTreeMap<K, V> {
+    /// The root never changes. When the root is split, its content
+    /// moves into a new child.
+    root: NodeVar<K, V>,
+}
+
+impl<K, V> Default for TBTreeMap<K, V>
+where
+    K: Any + Sync + Send + Clone + Ord,
+    V: Any + Sync + Send + Clone,
+{
+    fn default() -> Self {
+        TBTreeMap::new()
+    }
+}
+
+impl<K, V> TBTreeMap<K, V>
+where
+    K: Any + Sync + Send + Clone + Ord,
+    V: Any + Sync + Send + Clone,
+{
+    /// Create a new, empty map.
+    pub fn new() -> TBTreeMap<K, V> {
+        TBTreeMap { root: new_node(Node::Leaf(Vec::new())) }
+    }
+
+    /// Return the value associated with `key`.
+    pub fn get(&self, tx: &mut Transaction, key: &K) -> StmResult<Option<V>> {
+        let mut node = self.root.read(tx)?;
+        loop {
+            node = match *node {
+                Node::Leaf(ref entries) => {
+                    return Ok(
+                        entries
+                            .binary_search_by(|e| e.0.cmp(key))
+                            .ok()
+                            .map(|i| entries[i].1.clone()),
+                    );
+                }
+                Node::Inner(ref keys, ref children) => children[child_index(keys, key)].read(tx)?,
+            };
+        }
+    }
+
+    /// Check if the map contains `key`.
+    pub fn contains_key(&self, tx: &mut Transaction, key: &K) -> StmResult<bool> {
+        Ok(self.get(tx, key)?.is_some())
+    }
+
+    /// Insert a value and return the previous value for this key.
+    pub fn insert(&self, tx: &mut Transaction, key: K, value: V) -> StmResult<Option<V>> {
+        let (old, split) = insert_node(tx, &self.root, key, value)?;
+        if let Some((sep, right)) = split {
+            // Move the left half into a new node and make the
+            // root point to both halves.
+            let left = self.root.read(tx)?;
+            let inner = Node::Inner(vec![sep], vec![TVar::new(left), right]);
+            self.root.write(tx, Arc::new(inner))?;
+        }
+        Ok(old)
+    }
+
+    /// Remove `key` from the map and return its value.
+    pub fn remove(&self, tx: &mut Transaction, key: &K) -> StmResult<Option<V>> {
+        let old = remove_node(tx, &self.root, key)?;
+        if old.is_some() && is_empty_node(&*self.root.read(tx)?) {
+            self.root.write(tx, Arc::new(Node::Leaf(Vec::new())))?;
+        }
+        Ok(old)
+    }
+
+    /// Return the entry with the smallest key.
+    pub fn first(&self, tx: &mut Transaction) -> StmResult<Option<(K, V)>> {
+        edge_entry(tx, &self.root, false)
+    }
+
+    /// Return the entry with the largest key.
+    pub fn last(&self, tx: &mut Transaction) -> StmResult<Option<(K, V)>> {
+        edge_entry(tx, &self.root, true)
+    }
+
+    /// Remove the entry with the smallest key and return it.
+    pub fn pop_first(&self, tx: &mut Transaction) -> StmResult<Option<(K, V)>> {
+        let first = self.first(tx)?;
+        if let Some((ref k, _)) = first {
+            self.remove(tx, k)?;
+        }
+        Ok(first)
+    }
+
+    /// Remove the entry with the largest key and return it.
+    pub fn pop_last(&self, tx: &mut Transaction) -> StmResult<Option<(K, V)>> {
+        let last = self.last(tx)?;
+        if let Some((ref k, _)) = last {
+            self.remove(tx, k)?;
+        }
+        Ok(last)
+    }
+
+    /// Return all entries with keys inside of `range` in ascending order.
+    ///
+    /// Only the nodes overlapping `range` are read.
+    pub fn range<R>(&self, tx: &mut Transaction, range: R) -> StmResult<Vec<(K, V)>>
+    where
+        R: RangeBounds<K>,
+    {
+        let mut out = Vec::new();
+        collect_range(tx, &self.root, &range, &mut out)?;
+        Ok(out)
+    }
+
+    /// Return all entries in ascending order.
+    pub fn to_vec(&self, tx: &mut Transaction) -> StmResult<Vec<(K, V)>> {
+        self.range(tx, ..)
+    }
+
+    /// Move all entries with keys greater or equal to `key` into a new map.
+    pub fn split_off(&self, tx: &mut Transaction, key: &K) -> StmResult<TBTreeMap<K, V>> {
+        let other = TBTreeMap::new();
+        for (k, v) in self.range(tx, (Bound::Included(key), Bound::Unbounded))? {
+            self.remove(tx, &k)?;
+            other.insert(tx, k, v)?;
+        }
+        Ok(other)
+    }
+
+    /// Count the entries of the map.
+    ///
+    /// This reads the whole tree.
+    pub fn len(&self, tx: &mut Transaction) -> StmResult<usize> {
+        count(tx, &self.root)
+    }
+
+    /// Check if the map is empty.
+    pub fn is_empty(&self, tx: &mut Transaction) -> StmResult<bool> {
+        Ok(is_empty_node(&*self.root.read(tx)?))
+    }
+}
+
+fn new_node<K, V>(node: Node<K, V>) -> NodeVar<K, V>
+where
+    K: Any + Sync + Send + Clone,
+    V: Any + Sync + Send + Clone,
+{
+    TVar::new(Arc::new(node))
+}
+
+/// Return the index of the child, that may contain `key`.
+fn child_index<K: Ord>(keys: &[K], key: &K) -> usize {
+    match keys.binary_search(key) {
+        Ok(i) => i + 1,
+        Err(i) => i,
+    }
+}
+
+fn is_empty_node<K, V>(node: &Node<K, V>) -> bool {
+    match *node {
+        Node::Leaf(ref entries) => entries.is_empty(),
+        Node::Inner(_, ref children) => children.is_empty(),
+    }
+}
+
+fn insert_node<K, V>(
+    tx: &mut Transaction,
+    var: &NodeVar<K, V>,
+    key: K,
+    value: V,
+) -> StmResult<InsertResult<K, V>>
+where
+    K: Any + Sync + Send + Clone + Ord,
+    V: Any + Sync + Send + Clone,
+{
+    let node = var.read(tx)?;
+    match *node {
+        Node::Leaf(ref entries) => {
+            let mut entries = entries.clone();
+            let old = match entries.binary_search_by(|e| e.0.cmp(&key)) {
+                Ok(i) => Some(mem::replace(&mut entries[i].1, value)),
+                Err(i) => {
+                    entries.insert(i, (key, value));
+                    None
+                }
+            };
+            let split = if entries.len() > MAX_CHILDREN {
+                let right = entries.split_off(entries.len() / 2);
+                let sep = right[0].0.clone();
+                Some((sep, new_node(Node::Leaf(right))))
+            } else {
+                None
+            };
+            var.write(tx, Arc::new(Node::Leaf(entries)))?;
+            Ok((old, split))
+        }
+        Node::Inner(ref keys, ref children) => {
+            let i = child_index(keys, &key);
+            let (old, split) = insert_node(tx, &children[i], key, value)?;
+            let (sep, right) = match split {
+                Some(s) => s,
+                None => return Ok((old, None)),
+            };
+
+            let mut keys = keys.clone();
+            let mut children = children.clone();
+            keys.insert(i, sep);
+            children.insert(i + 1, right);
+            let split = if children.len() > MAX_CHILDREN {
+                let mid = keys.len() / 2;
+                let right_keys = keys.split_off(mid + 1);
+                let right_children = children.split_off(mid + 1);
+                let sep = keys.pop().unwrap();
+                Some((sep, new_node(Node::Inner(right_keys, right_children))))
+            } else {
+                None
+            };
+            var.write(tx, Arc::new(Node::Inner(keys, children)))?;
+            Ok((old, split))
+        }
+    }
+}
+
+fn remove_node<K, V>(tx: &mut Transaction, var: &NodeVar<K, V>, key: &K) -> StmResult<Option<V>>
+where
+    K: Any + Sync + Send + Clone + Ord,
+    V: Any + Sync + Send + Clone,
+{
+    let node = var.read(tx)?;
+    match *node {
+        Node::Leaf(ref entries) => match entries.binary_search_by(|e| e.0.cmp(key)) {
+            Ok(i) => {
+                let mut entries = entries.clone();
+                let (_, v) = entries.remove(i);
+                var.write(tx, Arc::new(Node::Leaf(entries)))?;
+                Ok(Some(v))
+            }
+            Err(_) => Ok(None),
+        },
+        Node::Inner(ref keys, ref children) => {
+            let i = child_index(keys, key);
+            let old = remove_node(tx, &children[i], key)?;
+            if old.is_some() && is_empty_node(&*children[i].read(tx)?) {
+                // Drop the empty child together with one of its separators.
+                let mut keys = keys.clone();
+                let mut children = children.clone();
+                children.remove(i);
+                if !keys.is_empty() {
+                    keys.remove(i.saturating_sub(1));
+                }
+                var.write(tx, Arc::new(Node::Inner(keys, children)))?;
+            }
+            Ok(old)
+        }
+    }
+}
+
+/// Return the first or, if `last` is set, the last entry of a subtree.
+fn edge_entry<K, V>(tx: &mut Transaction, var: &NodeVar<K, V>, last: bool) -> StmResult<Option<(K, V)>>
+where
+    K: Any + Sync + Send + Clone + Ord,
+    V: Any + Sync + Send + Clone,
+{
+    let node = var.read(tx)?;
+    match *node {
+        Node::Leaf(ref entries) => Ok(if last { entries.last() } else { entries.first() }.cloned()),
+        Node::Inner(_, ref children) => {
+            let child = if last { children.last() } else { children.first() };
+            match child {
+                Some(c) => edge_entry(tx, c, last),
+                None => Ok(None),
+            }
+        }
+    }
+}
+
+fn collect_range<K, V, R>(
+    tx: &mut Transaction,
+    var: &NodeVar<K, V>,
+    range: &R,
+    out: &mut Vec<(K, V)>,
+) -> StmResult<()>
+where
+    K: Any + Sync + Send + Clone + Ord,
+    V: Any + Sync + Send + Clone,
+    R: RangeBounds<K>,
+{
+    let node = var.read(tx)?;
+    match *node {
+        Node::Leaf(ref entries) => {
+            out.extend(entries.iter().filter(|e| range.contains(&e.0)).cloned());
+        }
+        Node::Inner(ref keys, ref children) => {
+            let lo = match range.start_bound() {
+                Bound::Included(k) | Bound::Excluded(k) => child_index(keys, k),
+                Bound::Unbounded => 0,
+            };
+            let hi = match range.end_bound() {
+                Bound::Included(k) | Bound::Excluded(k) => child_index(keys, k),
+                Bound::Unbounded => children.len().saturating_sub(1),
+            };
+            for c in children.iter().take(hi + 1).skip(lo) {
+                collect_range(tx, c, range, out)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn count<K, V>(tx: &mut Transaction, var: &NodeVar<K, V>) -> StmResult<usize>
+where
+    K: Any + Sync + Send + Clone + Ord,
+    V: Any + Sync + Send + Clone,
+{
+    let node = var.read(tx)?;
+    match *node {
+        Node::Leaf(ref entries) => Ok(entries.len()),
+        Node::Inner(_, ref children) => {
+            let mut n = 0;
+            for c in children {
+                n += count(tx, c)?;
+            }
+            Ok(n)
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use stm::*;
+    use super::*;
+
+    /// Check if inserted values can be read back.
+    #[test]
+    fn btree_insert_get() {
+        let map = TBTreeMap::new();
+        let x = atomically(|tx| {
+            assert_eq!(None, map.insert(tx, 1, 10)?);
+            assert_eq!(Some(10), map.insert(tx, 1, 11)?);
+            map.get(tx, &1)
+        });
+        assert_eq!(Some(11), x);
+        assert_eq!(None, atomically(|tx| map.get(tx, &2)));
+    }
+
+    /// Insert enough values in random order to split nodes multiple times
+    /// and check if the order is correct.
+    #[test]
+    fn btree_many() {
+        let map = TBTreeMap::new();
+        // 7919 is prime, so this visits every key in a scrambled order.
+        for i in 0..5000u64 {
+            let k = i * 7919 % 5000;
+            atomically(|tx| map.insert(tx, k, k * 2));
+        }
+
+        let all = atomically(|tx| map.to_vec(tx));
+        assert_eq!((0..5000).map(|k| (k, k * 2)).collect::<Vec<_>>(), all);
+        assert_eq!(5000, atomically(|tx| map.len(tx)));
+
+        for k in 0..5000 {
+            assert_eq!(Some(k * 2), atomically(|tx| map.get(tx, &k)));
+        }
+    }
+
+    /// Check `range` with different kinds of bounds.
+    #[test]
+    fn btree_range() {
+        let map = TBTreeMap::new();
+        atomically(|tx| {
+            for i in 0..1000 {
+                map.insert(tx, i, ())?;
+            }
+            Ok(())
+        });
+
+        let keys = |v: Vec<(i32, ())>| v.into_iter().map(|e| e.0).collect::<Vec<_>>();
+        assert_eq!((100..200).collect::<Vec<_>>(), keys(atomically(|tx| map.range(tx, 100..200))));
+        assert_eq!((100..=200).collect::<Vec<_>>(), keys(atomically(|tx| map.range(tx, 100..=200))));
+        assert_eq!((990..1000).collect::<Vec<_>>(), keys(atomically(|tx| map.range(tx, 990..))));
+        assert_eq!((0..5).collect::<Vec<_>>(), keys(atomically(|tx| map.range(tx, ..5))));
+        assert!(atomically(|tx| map.range(tx, 2000..)).is_empty());
+    }
+
+    /// Check `first`, `last` and the pop operations until the map is empty.
+    #[test]
+    fn btree_pop() {
+        let map = TBTreeMap::new();
+        atomically(|tx| {
+            for i in 0..100 {
+                map.insert(tx, i, i)?;
+            }
+            Ok(())
+        });
+
+        assert_eq!(Some((0, 0)), atomically(|tx| map.first(tx)));
+        assert_eq!(Some((99, 99)), atomically(|tx| map.last(tx)));
+        for i in 0..50 {
+            assert_eq!(Some((i, i)), atomically(|tx| map.pop_first(tx)));
+            assert_eq!(Some((99 - i, 99 - i)), atomically(|tx| map.pop_last(tx)));
+        }
+        assert_eq!(None, atomically(|tx| map.pop_first(tx)));
+        assert!(atomically(|tx| map.is_empty(tx)));
+
+        // The map is still usable after removing everything.
+        atomically(|tx| map.insert(tx, 1, 1));
+        assert_eq!(Some((1, 1)), atomically(|tx| map.last(tx)));
+    }
+
+    /// Check if `split_off` moves the upper half.
+    #[test]
+    fn btree_split_off() {
+        let map = TBTreeMap::new();
+        let other = atomically(|tx| {
+            for i in 0..100 {
+                map.insert(tx, i, i)?;
+            }
+            map.split_off(tx, &60)
+        });
+
+        assert_eq!(60, atomically(|tx| map.len(tx)));
+        assert_eq!(Some((59, 59)), atomically(|tx| map.last(tx)));
+        assert_eq!(40, atomically(|tx| other.len(tx)));
+        assert_eq!(Some((60, 60)), atomically(|tx| other.first(tx)));
+    }
+
+    /// Test if the map works with multiple concurrent threads.
+    #[test]
+    fn btree_threaded() {
+        use std::thread;
+        let map = TBTreeMap::new();
+
+        let handles: Vec<_> = (0..10)
+            .map(|i| {
+                let map2 = map.clone();
+                thread::spawn(move || for j in 0..100 {
+                    atomically(|tx| map2.insert(tx, j * 10 + i, i));
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+
+        let all = atomically(|tx| map.to_vec(tx));
+        assert_eq!((0..1000).map(|k| (k, k % 10)).collect::<Vec<_>>(), all);
+    }
+}
@@ -0,0 +1,174 @@
+use stm::*;
+use std::any::Any;
+use std::ops::RangeBounds;
+use super::TBTreeMap;
+
+/// `TBTreeSet` is a threadsafe ordered set, that uses software transactional memory.
+///
+/// It is a `TBTreeMap` without values, so it shares the same per-node `TVar`s.
+///
+///
+/// # Example
+///
+/// ```
+/// extern crate stm;
+/// extern crate stm_datastructures;
+///
+/// use stm::*;
+/// use stm_datastructures::TBTreeSet;
+///
+/// fn main() {
+///     let set = TBTreeSet::new();
+///     let x = atomically(|tx| {
+///         set.insert(tx, 2)?;
+///         set.insert(tx, 1)?;
+///         set.to_vec(tx)
+///     });
+///     assert_eq!(x, vec![1, 2]);
+/// }
+/// ```
+#[derive(Clone)]
+pub struct TBTreeSet<T> {
+    map: TBTreeMap<T, ()>,
+}
+
+impl<T: Any + Sync + Send + Clone + Ord> Default for TBTreeSet<T> {
+    fn default() -> Self {
+        TBTreeSet::new()
+    }
+}
+
+impl<T: Any + Sync + Send + Clone + Ord> TBTreeSet<T> {
+    /// Create a new, empty set.
+    pub fn new() -> TBTreeSet<T> {
+        TBTreeSet { map: TBTreeMap::new() }
+    }
+
+    /// Add a value to the set.
+    ///
+    /// Return `true` if the value was not present before.
+    pub fn insert(&self, tx: &mut Transaction, value: T) -> StmResult<bool> {
+        Ok(self.map.insert(tx, value, ())?.is_none())
+    }
+
+    /// Remove a value from the set.
+    ///
+    /// Return `true` if the value was present.
+    pub fn remove(&self, tx: &mut Transaction, value: &T) -> StmResult<bool> {
+        Ok(self.map.remove(tx, value)?.is_some())
+    }
+
+    /// Check if the set contains `value`.
+    pub fn contains(&self, tx: &mut Transaction, value: &T) -> StmResult<bool> {
+        self.map.contains_key(tx, value)
+    }
+
+    /// Count the elements of the set.
+    ///
+    /// This reads the whole tree.
+    pub fn len(&self, tx: &mut Transaction) -> StmResult<usize> {
+        self.map.len(tx)
+    }
+
+    /// Check if the set is empty.
+    pub fn is_empty(&self, tx: &mut Transaction) -> StmResult<bool> {
+        self.map.is_empty(tx)
+    }
+
+    /// Return the smallest element.
+    pub fn first(&self, tx: &mut Transaction) -> StmResult<Option<T>> {
+        Ok(self.map.first(tx)?.map(|e| e.0))
+    }
+
+    /// Return the largest element.
+    pub fn last(&self, tx: &mut Transaction) -> StmResult<Option<T>> {
+        Ok(self.map.last(tx)?.map(|e| e.0))
+    }
+
+    /// Return all elements inside of `range` in ascending order.
+    pub fn range<R>(&self, tx: &mut Transaction, range: R) -> StmResult<Vec<T>>
+    where
+        R: RangeBounds<T>,
+    {
+        Ok(self.map.range(tx, range)?.into_iter().map(|e| e.0).collect())
+    }
+
+    /// Return all elements in ascending order.
+    pub fn to_vec(&self, tx: &mut Transaction) -> StmResult<Vec<T>> {
+        self.range(tx, ..)
+    }
+
+    /// Insert all elements of `self` into `other`.
+    pub fn union_into(&self, tx: &mut Transaction, other: &TBTreeSet<T>) -> StmResult<()> {
+        for x in self.to_vec(tx)? {
+            other.insert(tx, x)?;
+        }
+        Ok(())
+    }
+
+    /// Return the elements of `self`, that are not in `other`, in ascending order.
+    pub fn difference(&self, tx: &mut Transaction, other: &TBTreeSet<T>) -> StmResult<Vec<T>> {
+        let mut out = Vec::new();
+        for x in self.to_vec(tx)? {
+            if !other.contains(tx, &x)? {
+                out.push(x);
+            }
+        }
+        Ok(out)
+    }
+
+    /// Return the elements, that are both in `self` and `other`, in ascending order.
+    pub fn intersection(&self, tx: &mut Transaction, other: &TBTreeSet<T>) -> StmResult<Vec<T>> {
+        let mut out = Vec::new();
+        for x in self.to_vec(tx)? {
+            if other.contains(tx, &x)? {
+                out.push(x);
+            }
+        }
+        Ok(out)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use stm::*;
+    use super::*;
+
+    /// Check insert, contains and remove.
+    #[test]
+    fn bset_insert_remove() {
+        let set = TBTreeSet::new();
+        atomically(|tx| {
+            assert!(set.insert(tx, 1)?);
+            assert!(!set.insert(tx, 1)?);
+            assert!(set.contains(tx, &1)?);
+            assert!(!set.contains(tx, &2)?);
+            Ok(())
+        });
+        assert_eq!(1, atomically(|tx| set.len(tx)));
+        assert!(atomically(|tx| set.remove(tx, &1)));
+        assert!(!atomically(|tx| set.remove(tx, &1)));
+        assert!(atomically(|tx| set.is_empty(tx)));
+    }
+
+    /// Check the set algebra.
+    #[test]
+    fn bset_algebra() {
+        let a = TBTreeSet::new();
+        let b = TBTreeSet::new();
+        atomically(|tx| {
+            for i in 0..10 {
+                a.insert(tx, i)?;
+                b.insert(tx, i + 5)?;
+            }
+            Ok(())
+        });
+
+        assert_eq!(vec![0, 1, 2, 3, 4], atomically(|tx| a.difference(tx, &b)));
+        assert_eq!(vec![5, 6, 7, 8, 9], atomically(|tx| a.intersection(tx, &b)));
+
+        atomically(|tx| a.union_into(tx, &b));
+        assert_eq!((0..15).collect::<Vec<_>>(), atomically(|tx| b.to_vec(tx)));
+    }
+}
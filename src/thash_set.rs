@@ -0,0 +1,174 @@
+use stm::*;
+use std::any::Any;
+use std::hash::Hash;
+use super::TMap;
+
+/// `THashSet` is a threadsafe hash set, that uses software transactional memory.
+///
+/// It is a `TMap` without values, so it uses the same buckets.
+/// Checking a single element only reads the bucket of that element.
+///
+///
+/// # Example
+///
+/// ```
+/// extern crate stm;
+/// extern crate stm_datastructures;
+///
+/// use stm::*;
+/// use stm_datastructures::THashSet;
+///
+/// fn main() {
+///     let set = THashSet::new();
+///     let x = atomically(|tx| {
+///         set.insert(tx, 42)?;
+///         set.contains(tx, &42)
+///     });
+///     assert!(x);
+/// }
+/// ```
+#[derive(Clone)]
+pub struct THashSet<T> {
+    map: TMap<T, ()>,
+}
+
+impl<T: Any + Sync + Send + Clone + Hash + Eq> Default for THashSet<T> {
+    fn default() -> Self {
+        THashSet::new()
+    }
+}
+
+impl<T: Any + Sync + Send + Clone + Hash + Eq> THashSet<T> {
+    /// Create a new, empty set.
+    pub fn new() -> THashSet<T> {
+        THashSet { map: TMap::new() }
+    }
+
+    /// Create a new, empty set with `n` buckets.
+    pub fn with_buckets(n: usize) -> THashSet<T> {
+        THashSet { map: TMap::with_buckets(n) }
+    }
+
+    /// Add a value to the set.
+    ///
+    /// Return `true` if the value was not present before.
+    pub fn insert(&self, tx: &mut Transaction, value: T) -> StmResult<bool> {
+        Ok(self.map.insert(tx, value, ())?.is_none())
+    }
+
+    /// Remove a value from the set.
+    ///
+    /// Return `true` if the value was present.
+    pub fn remove(&self, tx: &mut Transaction, value: &T) -> StmResult<bool> {
+        Ok(self.map.remove(tx, value)?.is_some())
+    }
+
+    /// Check if the set contains `value`.
+    pub fn contains(&self, tx: &mut Transaction, value: &T) -> StmResult<bool> {
+        self.map.contains_key(tx, value)
+    }
+
+    /// Count the elements of the set.
+    ///
+    /// This reads every bucket.
+    pub fn len(&self, tx: &mut Transaction) -> StmResult<usize> {
+        self.map.len(tx)
+    }
+
+    /// Check if the set is empty.
+    ///
+    /// This reads every bucket.
+    pub fn is_empty(&self, tx: &mut Transaction) -> StmResult<bool> {
+        self.map.is_empty(tx)
+    }
+
+    /// Return all elements in unspecified order.
+    pub fn to_vec(&self, tx: &mut Transaction) -> StmResult<Vec<T>> {
+        Ok(self.map.to_vec(tx)?.into_iter().map(|e| e.0).collect())
+    }
+
+    /// Insert all elements of `self` into `other`.
+    pub fn union_into(&self, tx: &mut Transaction, other: &THashSet<T>) -> StmResult<()> {
+        for x in self.to_vec(tx)? {
+            other.insert(tx, x)?;
+        }
+        Ok(())
+    }
+
+    /// Return the elements of `self`, that are not in `other`.
+    ///
+    /// Only the buckets of `other`, that may contain elements of `self`, are read.
+    pub fn difference(&self, tx: &mut Transaction, other: &THashSet<T>) -> StmResult<Vec<T>> {
+        let mut out = Vec::new();
+        for x in self.to_vec(tx)? {
+            if !other.contains(tx, &x)? {
+                out.push(x);
+            }
+        }
+        Ok(out)
+    }
+
+    /// Return the elements, that are both in `self` and `other`.
+    ///
+    /// Only the buckets of `other`, that may contain elements of `self`, are read.
+    pub fn intersection(&self, tx: &mut Transaction, other: &THashSet<T>) -> StmResult<Vec<T>> {
+        let mut out = Vec::new();
+        for x in self.to_vec(tx)? {
+            if other.contains(tx, &x)? {
+                out.push(x);
+            }
+        }
+        Ok(out)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use stm::*;
+    use super::*;
+
+    /// Check insert, contains and remove.
+    #[test]
+    fn hset_insert_remove() {
+        let set = THashSet::new();
+        atomically(|tx| {
+            assert!(set.insert(tx, 1)?);
+            assert!(!set.insert(tx, 1)?);
+            assert!(set.contains(tx, &1)?);
+            assert!(!set.contains(tx, &2)?);
+            Ok(())
+        });
+        assert_eq!(1, atomically(|tx| set.len(tx)));
+        assert!(atomically(|tx| set.remove(tx, &1)));
+        assert!(!atomically(|tx| set.remove(tx, &1)));
+        assert!(atomically(|tx| set.is_empty(tx)));
+    }
+
+    /// Check the set algebra.
+    #[test]
+    fn hset_algebra() {
+        let a = THashSet::new();
+        let b = THashSet::new();
+        atomically(|tx| {
+            for i in 0..10 {
+                a.insert(tx, i)?;
+                b.insert(tx, i + 5)?;
+            }
+            Ok(())
+        });
+
+        let mut d = atomically(|tx| a.difference(tx, &b));
+        d.sort();
+        assert_eq!(vec![0, 1, 2, 3, 4], d);
+
+        let mut i = atomically(|tx| a.intersection(tx, &b));
+        i.sort();
+        assert_eq!(vec![5, 6, 7, 8, 9], i);
+
+        atomically(|tx| a.union_into(tx, &b));
+        let mut u = atomically(|tx| b.to_vec(tx));
+        u.sort();
+        assert_eq!((0..15).collect::<Vec<_>>(), u);
+    }
+}
@@ -0,0 +1,233 @@
+use stm::*;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+use std::sync::atomic::{AtomicBool, Ordering as AtomicOrdering};
+use std::sync::{Arc, Condvar, Mutex, OnceLock};
+use std::thread;
+use std::time::{Duration, Instant};
+
+// All timeouts are served by a single background thread, that is started
+// on first use. It keeps the pending deadlines in a min-heap, sleeps until
+// the earliest one and then sets the corresponding flag in a transaction.
+// Setting the flag wakes up all transactions, that retried after reading it.
+//
+// Dropping the last handle of a timeout before its deadline sets its
+// `cancelled` flag. Cancelled entries are skipped when they reach the top of
+// the heap. To bound the memory used by long timeouts, that are dropped
+// early, the heap is compacted once more than half of it is cancelled.
+// The timer sets the flag of entries it has fired as well, so that dropping
+// them later does not count them again. All changes of the flags happen
+// while holding the lock, so the count stays exact.
+
+/// `Timeout` is a flag, that is set to `true` after a fixed duration.
+///
+/// Reading the flag inside of a transaction allows to give up a blocking
+/// operation after a deadline by combining it with `or`.
+///
+///
+/// # Example
+///
+/// ```
+/// extern crate stm;
+/// extern crate stm_datastructures;
+///
+/// use std::time::Duration;
+/// use stm::*;
+/// use stm_datastructures::{Queue, Timeout};
+///
+/// fn main() {
+///     let queue: Queue<i32> = Queue::new();
+///     let timeout = Timeout::after(Duration::from_millis(10));
+///     let x = atomically(|tx| {
+///         tx.or(
+///             |tx| Ok(queue.pop(tx)?.ok()),
+///             |tx| timeout.wait(tx).map(|_| None),
+///         )
+///     });
+///     assert_eq!(x, None);
+/// }
+/// ```
+#[derive(Clone)]
+pub struct Timeout {
+    inner: Arc<Inner>,
+}
+
+/// Shared by all clones of a `Timeout`. Cancels the deadline on drop.
+struct Inner {
+    expired: TVar<bool>,
+    cancelled: Arc<AtomicBool>,
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        timer().cancel(&self.cancelled);
+    }
+}
+
+impl Timeout {
+    /// Create a timeout, that expires after `duration`.
+    pub fn after(duration: Duration) -> Timeout {
+        Timeout::at(Instant::now() + duration)
+    }
+
+    /// Create a timeout, that expires at `deadline`.
+    pub fn at(deadline: Instant) -> Timeout {
+        let expired = TVar::new(false);
+        let cancelled = Arc::new(AtomicBool::new(false));
+        timer().add(Deadline {
+            at: deadline,
+            expired: expired.clone(),
+            cancelled: cancelled.clone(),
+        });
+        Timeout {
+            inner: Arc::new(Inner { expired, cancelled }),
+        }
+    }
+
+    /// Check if the timeout has expired.
+    pub fn expired(&self, tx: &mut Transaction) -> StmResult<bool> {
+        self.inner.expired.read(tx)
+    }
+
+    /// Retry until the timeout has expired.
+    pub fn wait(&self, tx: &mut Transaction) -> StmResult<()> {
+        guard(self.inner.expired.read(tx)?)
+    }
+}
+
+/// A pending timeout in the heap of the timer thread.
+struct Deadline {
+    at: Instant,
+    expired: TVar<bool>,
+    /// Set, when the timeout has been dropped or has fired.
+    cancelled: Arc<AtomicBool>,
+}
+
+impl PartialEq for Deadline {
+    fn eq(&self, other: &Self) -> bool {
+        self.at == other.at
+    }
+}
+
+impl Eq for Deadline {}
+
+impl PartialOrd for Deadline {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Deadline {
+    /// Reversed, so that the max-heap returns the earliest deadline first.
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.at.cmp(&self.at)
+    }
+}
+
+struct Timer {
+    state: Mutex<State>,
+    changed: Condvar,
+}
+
+struct State {
+    deadlines: BinaryHeap<Deadline>,
+    /// Number of cancelled entries in `deadlines`.
+    cancelled: usize,
+}
+
+/// Return the global timer and start its thread if necessary.
+fn timer() -> &'static Timer {
+    static TIMER: OnceLock<Timer> = OnceLock::new();
+    TIMER.get_or_init(|| {
+        thread::spawn(|| timer().run());
+        Timer {
+            state: Mutex::new(State {
+                deadlines: BinaryHeap::new(),
+                cancelled: 0,
+            }),
+            changed: Condvar::new(),
+        }
+    })
+}
+
+impl Timer {
+    fn add(&self, deadline: Deadline) {
+        self.state.lock().unwrap().deadlines.push(deadline);
+        self.changed.notify_one();
+    }
+
+    fn cancel(&self, cancelled: &AtomicBool) {
+        let mut state = self.state.lock().unwrap();
+        if cancelled.swap(true, AtomicOrdering::Relaxed) {
+            // Already fired.
+            return;
+        }
+        state.cancelled += 1;
+        if state.cancelled * 2 > state.deadlines.len() {
+            state.deadlines.retain(|d| !d.cancelled.load(AtomicOrdering::Relaxed));
+            state.cancelled = 0;
+        }
+    }
+
+    fn run(&self) {
+        let mut state = self.state.lock().unwrap();
+        loop {
+            let now = Instant::now();
+            let next = state.deadlines.peek().map(|d| d.at);
+            state = match next {
+                None => self.changed.wait(state).unwrap(),
+                Some(at) if at > now => self.changed.wait_timeout(state, at - now).unwrap().0,
+                Some(_) => {
+                    let d = state.deadlines.pop().unwrap();
+                    if d.cancelled.swap(true, AtomicOrdering::Relaxed) {
+                        state.cancelled -= 1;
+                        continue;
+                    }
+                    // Don't block new timeouts while committing.
+                    drop(state);
+                    atomically(|tx| d.expired.write(tx, true));
+                    self.state.lock().unwrap()
+                }
+            };
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use stm::*;
+    use super::*;
+
+    /// Timeouts expire in the order of their deadlines, independent of
+    /// the order of creation.
+    #[test]
+    fn timeout_order() {
+        let long = Timeout::after(Duration::from_secs(3600));
+        let short = Timeout::after(Duration::from_millis(10));
+        let start = Instant::now();
+        atomically(|tx| short.wait(tx));
+        assert!(start.elapsed() >= Duration::from_millis(5));
+        assert!(!atomically(|tx| long.expired(tx)));
+    }
+
+    /// Dropped timeouts don't stay in the heap until their deadline.
+    #[test]
+    fn timeout_cancel() {
+        let flags: Vec<_> = (0..100)
+            .map(|_| Timeout::after(Duration::from_secs(3600)).inner.cancelled.clone())
+            .collect();
+        // Only this vector and the heap can hold a flag.
+        let pending = flags.iter().filter(|f| Arc::strong_count(f) > 1).count();
+        assert!(pending <= 50, "{} cancelled timeouts left", pending);
+        assert!(flags.iter().all(|f| f.load(AtomicOrdering::Relaxed)));
+    }
+
+    /// A past deadline expires immediately.
+    #[test]
+    fn timeout_past() {
+        let timeout = Timeout::at(Instant::now());
+        atomically(|tx| timeout.wait(tx));
+        assert!(atomically(|tx| timeout.expired(tx)));
+    }
+}
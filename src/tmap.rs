@@ -0,0 +1,428 @@
+use stm::*;
+use std::any::Any;
+use std::hash::{BuildHasher, Hash};
+use std::collections::hash_map::RandomState;
+use std::sync::Arc;
+use super::arcmap::ArcMap;
+
+// `TMap` distributes its entries over a fixed number of buckets.
+// Every bucket is a separate `TVar` holding an `ArcMap` of entries.
+// A transaction only reads and writes the buckets of the keys it touches,
+// so transactions on disjoint keys rarely conflict.
+//
+// The number of buckets never changes. Growing the table would require
+// a single variable, that every transaction has to read, which is
+// exactly the contention this map tries to avoid. Instead every bucket
+// is a persistent hash trie, so a write copies `O(log n)` nodes even if
+// the map holds far more entries than it has buckets. What does not
+// scale is the number of conflicts: with many more writers than buckets
+// transactions collide, so `with_buckets` should be used for large maps.
+//
+// The buckets use their own hasher. With the hasher, that selects the
+// bucket, all keys of a bucket would share the lowest bits of their hash
+// and the first levels of the trie would only have a single child.
+
+/// Number of buckets used by `TMap::new`.
+const DEFAULT_BUCKETS: usize = 64;
+
+/// A single bucket of the map.
+type Bucket<K, V> = ArcMap<K, V>;
+
+/// `TMap` is a threadsafe hash map, that uses software transactional memory.
+///
+/// Unlike a `HashMap` inside of a single `TVar`, transactions that access
+/// different keys usually don't conflict.
+///
+/// The number of buckets is fixed when the map is created. `new` uses 64,
+/// which suits small maps. Concurrent writers to larger maps conflict
+/// more often, so create these with `with_buckets` and a bucket count in
+/// the order of the expected number of entries.
+///
+///
+/// # Example
+///
+/// ```
+/// extern crate stm;
+/// extern crate stm_datastructures;
+///
+/// use stm::*;
+/// use stm_datastructures::TMap;
+///
+/// fn main() {
+///     let map = TMap::new();
+///     let x = atomically(|tx| {
+///         map.insert(tx, "answer", 42)?;
+///         map.get(tx, &"answer")
+///     });
+///     assert_eq!(x, Some(42));
+/// }
+/// ```
+#[derive(Clone)]
+pub struct TMap<K, V> {
+    buckets: Arc<Vec<TVar<Bucket<K, V>>>>,
+    hasher: RandomState,
+}
+
+impl<K, V> Default for TMap<K, V>
+where
+    K: Any + Sync + Send + Clone + Hash + Eq,
+    V: Any + Sync + Send + Clone,
+{
+    fn default() -> Self {
+        TMap::new()
+    }
+}
+
+impl<K, V> TMap<K, V>
+where
+    K: Any + Sync + Send + Clone + Hash + Eq,
+    V: Any + Sync + Send + Clone,
+{
+    /// Create a new, empty map with a small number of buckets.
+    ///
+    /// Use `with_buckets` for maps, that will hold many entries.
+    pub fn new() -> TMap<K, V> {
+        TMap::with_buckets(DEFAULT_BUCKETS)
+    }
+
+    /// Create a new, empty map with `n` buckets.
+    ///
+    /// This is the recommended constructor for maps, that are expected to
+    /// grow large, because the bucket count never changes. More buckets
+    /// reduce the chance of conflicts between transactions, but make
+    /// operations on the whole map like `len` more expensive.
+    pub fn with_buckets(n: usize) -> TMap<K, V> {
+        assert!(n > 0, "TMap needs at least one bucket");
+        let inner = RandomState::new();
+        TMap {
+            buckets: Arc::new(
+                (0..n)
+                    .map(|_| TVar::new(ArcMap::with_hasher(inner.clone())))
+                    .collect(),
+            ),
+            hasher: RandomState::new(),
+        }
+    }
+
+    /// Return the bucket, that may contain `key`.
+    fn bucket(&self, key: &K) -> &TVar<Bucket<K, V>> {
+        let i = self.hasher.hash_one(key) as usize % self.buckets.len();
+        &self.buckets[i]
+    }
+
+    /// Return the value associated with `key`.
+    pub fn get(&self, tx: &mut Transaction, key: &K) -> StmResult<Option<V>> {
+        Ok(self.bucket(key).read(tx)?.get(key).cloned())
+    }
+
+    /// Check if the map contains `key`.
+    pub fn contains_key(&self, tx: &mut Transaction, key: &K) -> StmResult<bool> {
+        Ok(self.bucket(key).read(tx)?.contains_key(key))
+    }
+
+    /// Insert a value and return the previous value for this key.
+    pub fn insert(&self, tx: &mut Transaction, key: K, value: V) -> StmResult<Option<V>> {
+        let var = self.bucket(&key);
+        let mut bucket = var.read(tx)?;
+        let old = bucket.insert(key, value);
+        var.write(tx, bucket)?;
+        Ok(old)
+    }
+
+    /// Remove `key` from the map and return its value.
+    pub fn remove(&self, tx: &mut Transaction, key: &K) -> StmResult<Option<V>> {
+        let var = self.bucket(key);
+        let mut bucket = var.read(tx)?;
+        let old = bucket.remove(key);
+        // Don't write the bucket if nothing changed, so that
+        // we don't cause conflicts with other transactions.
+        if old.is_some() {
+            var.write(tx, bucket)?;
+        }
+        Ok(old)
+    }
+
+    /// Replace the value of `key` with the result of `f`.
+    ///
+    /// Return `false` and leave the map untouched if `key` is not present.
+    pub fn modify<F>(&self, tx: &mut Transaction, key: &K, f: F) -> StmResult<bool>
+    where
+        F: FnOnce(V) -> V,
+    {
+        let var = self.bucket(key);
+        let mut bucket = var.read(tx)?;
+        let new = match bucket.get(key) {
+            Some(v) => f(v.clone()),
+            None => return Ok(false),
+        };
+        bucket.insert(key.clone(), new);
+        var.write(tx, bucket)?;
+        Ok(true)
+    }
+
+    /// Get the entry of `key` for in-place manipulation.
+    pub fn entry(&self, tx: &mut Transaction, key: K) -> StmResult<Entry<'_, K, V>> {
+        Ok(match self.get(tx, &key)? {
+            Some(value) => Entry::Occupied(OccupiedEntry {
+                map: self,
+                key,
+                value,
+            }),
+            None => Entry::Vacant(VacantEntry { map: self, key }),
+        })
+    }
+
+    /// Return all entries of the map in unspecified order.
+    ///
+    /// This reads every bucket.
+    pub fn to_vec(&self, tx: &mut Transaction) -> StmResult<Vec<(K, V)>> {
+        let mut out = Vec::new();
+        for var in self.buckets.iter() {
+            out.extend(var.read(tx)?.iter().map(|(k, v)| (k.clone(), v.clone())));
+        }
+        Ok(out)
+    }
+
+    /// Count the entries of the map.
+    ///
+    /// This reads every bucket and therefore conflicts with all
+    /// writing transactions.
+    pub fn len(&self, tx: &mut Transaction) -> StmResult<usize> {
+        let mut n = 0;
+        for var in self.buckets.iter() {
+            n += var.read(tx)?.len();
+        }
+        Ok(n)
+    }
+
+    /// Check if the map is empty.
+    ///
+    /// Like `len` this reads every bucket.
+    pub fn is_empty(&self, tx: &mut Transaction) -> StmResult<bool> {
+        for var in self.buckets.iter() {
+            if !var.read(tx)?.is_empty() {
+                return Ok(false);
+            }
+        }
+        Ok(true)
+    }
+}
+
+/// A view into a single entry of a `TMap`.
+///
+/// Created by `TMap::entry`.
+pub enum Entry<'a, K: 'a, V: 'a> {
+    Occupied(OccupiedEntry<'a, K, V>),
+    Vacant(VacantEntry<'a, K, V>),
+}
+
+/// An entry, that is present in the map.
+pub struct OccupiedEntry<'a, K: 'a, V: 'a> {
+    map: &'a TMap<K, V>,
+    key: K,
+    value: V,
+}
+
+/// An entry, that is missing in the map.
+pub struct VacantEntry<'a, K: 'a, V: 'a> {
+    map: &'a TMap<K, V>,
+    key: K,
+}
+
+impl<'a, K, V> Entry<'a, K, V>
+where
+    K: Any + Sync + Send + Clone + Hash + Eq,
+    V: Any + Sync + Send + Clone,
+{
+    /// Return the key of the entry.
+    pub fn key(&self) -> &K {
+        match *self {
+            Entry::Occupied(ref e) => &e.key,
+            Entry::Vacant(ref e) => &e.key,
+        }
+    }
+
+    /// Insert `default` if the entry is vacant and return the value.
+    pub fn or_insert(self, tx: &mut Transaction, default: V) -> StmResult<V> {
+        self.or_insert_with(tx, || default)
+    }
+
+    /// Insert the result of `f` if the entry is vacant and return the value.
+    pub fn or_insert_with<F>(self, tx: &mut Transaction, f: F) -> StmResult<V>
+    where
+        F: FnOnce() -> V,
+    {
+        match self {
+            Entry::Occupied(e) => Ok(e.value),
+            Entry::Vacant(e) => {
+                let value = f();
+                e.insert(tx, value.clone())?;
+                Ok(value)
+            }
+        }
+    }
+
+    /// Modify the value if the entry is occupied.
+    pub fn and_modify<F>(self, tx: &mut Transaction, f: F) -> StmResult<Self>
+    where
+        F: FnOnce(V) -> V,
+    {
+        Ok(match self {
+            Entry::Occupied(mut e) => {
+                let value = f(e.value.clone());
+                e.insert(tx, value)?;
+                Entry::Occupied(e)
+            }
+            vacant => vacant,
+        })
+    }
+}
+
+impl<'a, K, V> OccupiedEntry<'a, K, V>
+where
+    K: Any + Sync + Send + Clone + Hash + Eq,
+    V: Any + Sync + Send + Clone,
+{
+    /// Return the key of the entry.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Return the current value of the entry.
+    pub fn get(&self) -> &V {
+        &self.value
+    }
+
+    /// Replace the value and return the old one.
+    pub fn insert(&mut self, tx: &mut Transaction, value: V) -> StmResult<V> {
+        self.map.insert(tx, self.key.clone(), value.clone())?;
+        Ok(::std::mem::replace(&mut self.value, value))
+    }
+
+    /// Remove the entry from the map and return its value.
+    pub fn remove(self, tx: &mut Transaction) -> StmResult<V> {
+        self.map.remove(tx, &self.key)?;
+        Ok(self.value)
+    }
+}
+
+impl<'a, K, V> VacantEntry<'a, K, V>
+where
+    K: Any + Sync + Send + Clone + Hash + Eq,
+    V: Any + Sync + Send + Clone,
+{
+    /// Return the key of the entry.
+    pub fn key(&self) -> &K {
+        &self.key
+    }
+
+    /// Insert a value for the key of the entry.
+    pub fn insert(self, tx: &mut Transaction, value: V) -> StmResult<()> {
+        self.map.insert(tx, self.key, value)?;
+        Ok(())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use stm::*;
+    use super::*;
+
+    /// Check if inserted values can be read back.
+    #[test]
+    fn tmap_insert_get() {
+        let map = TMap::new();
+        let x = atomically(|tx| {
+            assert_eq!(None, map.insert(tx, 1, 10)?);
+            assert_eq!(Some(10), map.insert(tx, 1, 11)?);
+            map.get(tx, &1)
+        });
+        assert_eq!(Some(11), x);
+        assert_eq!(None, atomically(|tx| map.get(tx, &2)));
+    }
+
+    /// Check if `remove` and `len` work with colliding keys.
+    #[test]
+    fn tmap_remove_len() {
+        // A single bucket forces all keys into the same trie.
+        let map = TMap::with_buckets(1);
+        atomically(|tx| {
+            for i in 0..10 {
+                map.insert(tx, i, i * 2)?;
+            }
+            Ok(())
+        });
+
+        assert_eq!(10, atomically(|tx| map.len(tx)));
+        assert_eq!(Some(10), atomically(|tx| map.remove(tx, &5)));
+        assert_eq!(None, atomically(|tx| map.remove(tx, &5)));
+        assert_eq!(9, atomically(|tx| map.len(tx)));
+        assert!(!atomically(|tx| map.contains_key(tx, &5)));
+        for i in (0..10).filter(|&i| i != 5) {
+            assert_eq!(Some(i * 2), atomically(|tx| map.get(tx, &i)));
+        }
+    }
+
+    /// Many more entries than buckets.
+    #[test]
+    fn tmap_large() {
+        let map = TMap::with_buckets(4);
+        for i in 0..10_000 {
+            atomically(|tx| map.insert(tx, i, i).map(|_| ()));
+        }
+        assert_eq!(10_000, atomically(|tx| map.len(tx)));
+        for i in (0..10_000).step_by(7) {
+            assert_eq!(Some(i), atomically(|tx| map.remove(tx, &i)));
+        }
+        for i in 0..10_000 {
+            let expected = if i % 7 == 0 { None } else { Some(i) };
+            assert_eq!(expected, atomically(|tx| map.get(tx, &i)));
+        }
+    }
+
+    /// Check `modify` and the entry API.
+    #[test]
+    fn tmap_modify_entry() {
+        let map = TMap::new();
+        let x = atomically(|tx| {
+            assert!(!map.modify(tx, &"a", |v| v + 1)?);
+            map.entry(tx, "a")?.or_insert(tx, 1)?;
+            map.entry(tx, "a")?.and_modify(tx, |v| v + 1)?.or_insert(tx, 0)?;
+            assert!(map.modify(tx, &"a", |v| v * 10)?);
+            map.get(tx, &"a")
+        });
+        assert_eq!(Some(20), x);
+
+        let removed = atomically(|tx| match map.entry(tx, "a")? {
+            Entry::Occupied(e) => e.remove(tx).map(Some),
+            Entry::Vacant(_) => Ok(None),
+        });
+        assert_eq!(Some(20), removed);
+        assert!(atomically(|tx| map.is_empty(tx)));
+    }
+
+    /// Test if the map works with multiple concurrent threads.
+    #[test]
+    fn tmap_threaded() {
+        use std::thread;
+        let map = TMap::new();
+
+        let handles: Vec<_> = (0..10)
+            .map(|i| {
+                let map2 = map.clone();
+                thread::spawn(move || for j in 0..10 {
+                    atomically(|tx| map2.insert(tx, i * 10 + j, i));
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+
+        assert_eq!(100, atomically(|tx| map.len(tx)));
+        for k in 0..100 {
+            assert_eq!(Some(k / 10), atomically(|tx| map.get(tx, &k)));
+        }
+    }
+}
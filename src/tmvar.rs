@@ -0,0 +1,139 @@
+use stm::*;
+use std::any::Any;
+
+/// `TMVar` is a threadsafe slot, that is either empty or holds a single value.
+///
+/// It is similar to Haskell's `TMVar` and can be used for handing a value
+/// from one thread to another or for publishing a one-shot result.
+///
+///
+/// # Example
+///
+/// ```
+/// extern crate stm;
+/// extern crate stm_datastructures;
+///
+/// use stm::*;
+/// use stm_datastructures::TMVar;
+///
+/// fn main() {
+///     let var = TMVar::new_empty();
+///     let x = atomically(|tx| {
+///         var.put(tx, 42)?;
+///         var.take(tx)
+///     });
+///     assert_eq!(x, 42);
+/// }
+/// ```
+#[derive(Clone)]
+pub struct TMVar<T> {
+    slot: TVar<Option<T>>,
+}
+
+impl<T: Any + Sync + Clone + Send> TMVar<T> {
+    /// Create a `TMVar` holding `value`.
+    pub fn new(value: T) -> TMVar<T> {
+        TMVar { slot: TVar::new(Some(value)) }
+    }
+
+    /// Create an empty `TMVar`.
+    pub fn new_empty() -> TMVar<T> {
+        TMVar { slot: TVar::new(None) }
+    }
+
+    /// Take the value out and leave the `TMVar` empty.
+    pub fn try_take(&self, tx: &mut Transaction) -> StmResult<Option<T>> {
+        let v = self.slot.read(tx)?;
+        if v.is_some() {
+            self.slot.write(tx, None)?;
+        }
+        Ok(v)
+    }
+
+    /// Take the value out or retry if the `TMVar` is empty.
+    pub fn take(&self, tx: &mut Transaction) -> StmResult<T> {
+        unwrap_or_retry(self.try_take(tx)?)
+    }
+
+    /// Store `value` if the `TMVar` is empty.
+    ///
+    /// Return `false` and drop `value` if it is full.
+    pub fn try_put(&self, tx: &mut Transaction, value: T) -> StmResult<bool> {
+        if self.slot.read(tx)?.is_some() {
+            return Ok(false);
+        }
+        self.slot.write(tx, Some(value))?;
+        Ok(true)
+    }
+
+    /// Store `value` or retry if the `TMVar` is full.
+    pub fn put(&self, tx: &mut Transaction, value: T) -> StmResult<()> {
+        guard(self.slot.read(tx)?.is_none())?;
+        self.slot.write(tx, Some(value))
+    }
+
+    /// Return the value without taking it.
+    pub fn try_read(&self, tx: &mut Transaction) -> StmResult<Option<T>> {
+        self.slot.read(tx)
+    }
+
+    /// Return the value without taking it or retry if the `TMVar` is empty.
+    pub fn read(&self, tx: &mut Transaction) -> StmResult<T> {
+        unwrap_or_retry(self.slot.read(tx)?)
+    }
+
+    /// Replace the value and return the old one.
+    ///
+    /// Retry if the `TMVar` is empty.
+    pub fn swap(&self, tx: &mut Transaction, value: T) -> StmResult<T> {
+        let old = self.read(tx)?;
+        self.slot.write(tx, Some(value))?;
+        Ok(old)
+    }
+
+    /// Check if the `TMVar` is empty.
+    pub fn is_empty(&self, tx: &mut Transaction) -> StmResult<bool> {
+        Ok(self.slot.read(tx)?.is_none())
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use stm::*;
+    use super::*;
+
+    /// Check the non-blocking operations.
+    #[test]
+    fn tmvar_try() {
+        let var = TMVar::new(1);
+        atomically(|tx| {
+            assert!(!var.try_put(tx, 2)?);
+            assert_eq!(1, var.swap(tx, 3)?);
+            assert_eq!(3, var.read(tx)?);
+            assert_eq!(Some(3), var.try_take(tx)?);
+            assert_eq!(None, var.try_take(tx)?);
+            assert!(var.is_empty(tx)?);
+            assert!(var.try_put(tx, 4)?);
+            Ok(())
+        });
+        assert_eq!(4, atomically(|tx| var.take(tx)));
+    }
+
+    /// Hand values between two threads. Each `put` has to wait until the
+    /// previous value has been taken.
+    #[test]
+    fn tmvar_threaded() {
+        use std::thread;
+        let var = TMVar::new_empty();
+        let var2 = var.clone();
+
+        thread::spawn(move || for i in 0..10 {
+            atomically(|tx| var2.put(tx, i));
+        });
+
+        for i in 0..10 {
+            assert_eq!(i, atomically(|tx| var.take(tx)));
+        }
+    }
+}
@@ -0,0 +1,283 @@
+use stm::*;
+use std::any::Any;
+use std::sync::Arc;
+
+// The elements are stored in chunks of `CHUNK_SIZE` elements. Each chunk
+// is its own `TVar`, so that transactions, that work on different chunks,
+// don't conflict. All chunks but the last one are full and the last one is
+// never empty. Therefore the length can be computed from the number of
+// chunks and the length of the last chunk.
+//
+// The list of chunks only changes, when a chunk is added or removed, which
+// happens once every `CHUNK_SIZE` pushes or pops. `push` and `pop`
+// otherwise only touch the last chunk.
+//
+// Chunk contents are immutable `Arc`s. Writes copy a single chunk and
+// `snapshot` shares the chunk contents with the copy. It can't be a single
+// read though: every chunk is a variable of its own, so the snapshot has to
+// read all of them and create a new variable for each. That is the price
+// for appends not conflicting with writes to other chunks.
+
+const CHUNK_SIZE: usize = 32;
+
+type Chunk<T> = TVar<Arc<Vec<T>>>;
+
+/// `TVec` is a threadsafe growable array, that uses software transactional memory.
+///
+/// Transactions, that access different parts of the vector, don't conflict
+/// with each other. Appends only conflict with other appends and with
+/// accesses to the last few elements.
+///
+///
+/// # Example
+///
+/// ```
+/// extern crate stm;
+/// extern crate stm_datastructures;
+///
+/// use stm::*;
+/// use stm_datastructures::TVec;
+///
+/// fn main() {
+///     let vec = TVec::new();
+///     let x = atomically(|tx| {
+///         vec.extend(tx, 0..10)?;
+///         vec.set(tx, 3, 42)?;
+///         vec.pop(tx)?;
+///         Ok((vec.get(tx, 3)?, vec.len(tx)?))
+///     });
+///     assert_eq!(x, (Some(42), 9));
+/// }
+/// ```
+#[derive(Clone)]
+pub struct TVec<T> {
+    chunks: TVar<Arc<Vec<Chunk<T>>>>,
+}
+
+impl<T: Any + Sync + Clone + Send> Default for TVec<T> {
+    fn default() -> Self {
+        TVec::new()
+    }
+}
+
+impl<T: Any + Sync + Clone + Send> TVec<T> {
+    /// Create an empty vector.
+    pub fn new() -> TVec<T> {
+        TVec { chunks: TVar::new(Arc::new(Vec::new())) }
+    }
+
+    /// Return the number of elements.
+    pub fn len(&self, tx: &mut Transaction) -> StmResult<usize> {
+        let chunks = self.chunks.read(tx)?;
+        Ok(match chunks.last() {
+            Some(last) => (chunks.len() - 1) * CHUNK_SIZE + last.read(tx)?.len(),
+            None => 0,
+        })
+    }
+
+    /// Check if the vector is empty.
+    pub fn is_empty(&self, tx: &mut Transaction) -> StmResult<bool> {
+        Ok(self.chunks.read(tx)?.is_empty())
+    }
+
+    /// Return the element at `index` or `None` if it is out of bounds.
+    pub fn get(&self, tx: &mut Transaction, index: usize) -> StmResult<Option<T>> {
+        let chunks = self.chunks.read(tx)?;
+        Ok(match chunks.get(index / CHUNK_SIZE) {
+            Some(chunk) => chunk.read(tx)?.get(index % CHUNK_SIZE).cloned(),
+            None => None,
+        })
+    }
+
+    /// Replace the element at `index` and return the old one.
+    ///
+    /// Return `None` and drop `value`, if `index` is out of bounds.
+    pub fn set(&self, tx: &mut Transaction, index: usize, value: T) -> StmResult<Option<T>> {
+        let chunks = self.chunks.read(tx)?;
+        let chunk = match chunks.get(index / CHUNK_SIZE) {
+            Some(chunk) => chunk,
+            None => return Ok(None),
+        };
+        let mut values = chunk.read(tx)?;
+        let old = match Arc::make_mut(&mut values).get_mut(index % CHUNK_SIZE) {
+            Some(x) => ::std::mem::replace(x, value),
+            None => return Ok(None),
+        };
+        chunk.write(tx, values)?;
+        Ok(Some(old))
+    }
+
+    /// Add an element to the end.
+    pub fn push(&self, tx: &mut Transaction, value: T) -> StmResult<()> {
+        self.extend(tx, Some(value))
+    }
+
+    /// Remove the last element.
+    pub fn pop(&self, tx: &mut Transaction) -> StmResult<Option<T>> {
+        let mut chunks = self.chunks.read(tx)?;
+        let last = match chunks.last() {
+            Some(last) => last.clone(),
+            None => return Ok(None),
+        };
+        let mut values = last.read(tx)?;
+        let x = Arc::make_mut(&mut values).pop();
+        if values.is_empty() {
+            Arc::make_mut(&mut chunks).pop();
+            self.chunks.write(tx, chunks)?;
+        } else {
+            last.write(tx, values)?;
+        }
+        Ok(x)
+    }
+
+    /// Add all elements of `iter` to the end.
+    pub fn extend<I>(&self, tx: &mut Transaction, iter: I) -> StmResult<()>
+    where
+        I: IntoIterator<Item = T>,
+    {
+        let mut iter = iter.into_iter().peekable();
+        if iter.peek().is_none() {
+            return Ok(());
+        }
+        let mut chunks = self.chunks.read(tx)?;
+
+        // Fill up the last chunk first.
+        if let Some(last) = chunks.last() {
+            let mut values = last.read(tx)?;
+            if values.len() < CHUNK_SIZE {
+                let free = CHUNK_SIZE - values.len();
+                Arc::make_mut(&mut values).extend(iter.by_ref().take(free));
+                last.write(tx, values)?;
+            }
+        }
+
+        if iter.peek().is_some() {
+            let new = Arc::make_mut(&mut chunks);
+            while iter.peek().is_some() {
+                let values: Vec<T> = iter.by_ref().take(CHUNK_SIZE).collect();
+                new.push(TVar::new(Arc::new(values)));
+            }
+            self.chunks.write(tx, chunks)?;
+        }
+        Ok(())
+    }
+
+    /// Shorten the vector to `len` elements.
+    ///
+    /// Do nothing if it is already shorter.
+    pub fn truncate(&self, tx: &mut Transaction, len: usize) -> StmResult<()> {
+        let mut chunks = self.chunks.read(tx)?;
+        let keep = len.div_ceil(CHUNK_SIZE);
+        if keep < chunks.len() {
+            Arc::make_mut(&mut chunks).truncate(keep);
+            self.chunks.write(tx, chunks.clone())?;
+        }
+        if let Some(last) = chunks.last() {
+            let mut values = last.read(tx)?;
+            let rest = len - (chunks.len() - 1) * CHUNK_SIZE;
+            if rest < values.len() {
+                Arc::make_mut(&mut values).truncate(rest);
+                last.write(tx, values)?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Return all elements.
+    pub fn to_vec(&self, tx: &mut Transaction) -> StmResult<Vec<T>> {
+        let mut out = Vec::new();
+        for chunk in self.chunks.read(tx)?.iter() {
+            out.extend(chunk.read(tx)?.iter().cloned());
+        }
+        Ok(out)
+    }
+
+    /// Create an independent copy of the vector.
+    ///
+    /// The copy shares the elements with `self` until either of them is
+    /// changed, so no element is cloned. It still reads and creates one
+    /// variable per chunk of 32 elements, so this takes `O(len / 32)` time
+    /// and conflicts with every transaction, that writes to the vector.
+    pub fn snapshot(&self, tx: &mut Transaction) -> StmResult<TVec<T>> {
+        let mut chunks = Vec::new();
+        for chunk in self.chunks.read(tx)?.iter() {
+            chunks.push(TVar::new(chunk.read(tx)?));
+        }
+        Ok(TVec { chunks: TVar::new(Arc::new(chunks)) })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use stm::*;
+    use super::*;
+
+    /// Compare the operations with a `Vec` across chunk boundaries.
+    #[test]
+    fn tvec_ops() {
+        let vec = TVec::new();
+        let mut expected: Vec<usize> = Vec::new();
+        atomically(|tx| vec.extend(tx, 0..70));
+        expected.extend(0..70);
+        for i in 70..100 {
+            atomically(|tx| vec.push(tx, i));
+            expected.push(i);
+        }
+        assert_eq!(expected, atomically(|tx| vec.to_vec(tx)));
+        assert_eq!(100, atomically(|tx| vec.len(tx)));
+
+        assert_eq!(Some(40), atomically(|tx| vec.set(tx, 40, 1000)));
+        expected[40] = 1000;
+        assert_eq!(None, atomically(|tx| vec.set(tx, 100, 0)));
+        assert_eq!(Some(1000), atomically(|tx| vec.get(tx, 40)));
+        assert_eq!(None, atomically(|tx| vec.get(tx, 100)));
+
+        for _ in 0..5 {
+            assert_eq!(expected.pop(), atomically(|tx| vec.pop(tx)));
+        }
+        for &len in &[96, 64, 63, 10, 20, 0] {
+            atomically(|tx| vec.truncate(tx, len));
+            expected.truncate(len);
+            assert_eq!(expected, atomically(|tx| vec.to_vec(tx)));
+            assert_eq!(expected.len(), atomically(|tx| vec.len(tx)));
+        }
+        assert!(atomically(|tx| vec.is_empty(tx)));
+        assert_eq!(None, atomically(|tx| vec.pop(tx)));
+    }
+
+    /// A snapshot is not affected by later changes.
+    #[test]
+    fn tvec_snapshot() {
+        let vec = TVec::new();
+        atomically(|tx| vec.extend(tx, 0..40));
+        let copy = atomically(|tx| vec.snapshot(tx));
+        atomically(|tx| {
+            vec.set(tx, 0, 100)?;
+            vec.truncate(tx, 10)
+        });
+        assert_eq!((0..40).collect::<Vec<_>>(), atomically(|tx| copy.to_vec(tx)));
+        assert_eq!(Some(100), atomically(|tx| vec.get(tx, 0)));
+    }
+
+    /// Concurrent pushes don't get lost.
+    #[test]
+    fn tvec_threaded() {
+        use std::thread;
+        let vec = TVec::new();
+        let handles: Vec<_> = (0..4)
+            .map(|i| {
+                let vec = vec.clone();
+                thread::spawn(move || for j in 0..50 {
+                    atomically(|tx| vec.push(tx, i * 50 + j));
+                })
+            })
+            .collect();
+        for h in handles {
+            h.join().unwrap();
+        }
+        let mut all = atomically(|tx| vec.to_vec(tx));
+        all.sort();
+        assert_eq!((0..200).collect::<Vec<_>>(), all);
+    }
+}